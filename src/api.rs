@@ -1,9 +1,8 @@
 use std::collections::HashMap;
 use std::convert::From;
 use std::error::Error;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::PathBuf;
+use std::fs::{create_dir_all, metadata, rename};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use chrono::offset::Utc;
@@ -11,20 +10,41 @@ use chrono::{DateTime, SecondsFormat::Secs};
 use futures_util::StreamExt;
 use geojson::{Feature, FeatureCollection, GeoJson};
 use log::{debug, info, error};
-use reqwest::{Client, Response};
+use reqwest::header::{AUTHORIZATION, CONTENT_RANGE, LOCATION, RANGE};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Response, StatusCode};
 use serde::{Serialize, Deserialize};
-use spinners::{Spinner, Spinners};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use url::Url;
 
-use crate::Credentials;
-use crate::args::{DownloadArgs, SearchArgs};
-use crate::util::{get_id, get_value, from_path, print_over};
-
+use crate::credentials::{Credentials, Grant};
+use crate::args::{Backend, PublishedSince, SearchArgs};
+use crate::naming::{ServerNames, disposition_filename, initial_path, planned_path};
+use crate::odata::search_products;
+use crate::progress;
+use crate::sentinel1::Sentinel1Args;
+use crate::throttle::RateLimiter;
+use crate::util::{get_id, get_value, from_path};
+
+// Client used for user accounts
+const PUBLIC_CLIENT_ID: &str = "cdse-public";
 // POST
 const AUTH_URL: &str = "https://identity.dataspace.copernicus.eu/auth/realms/CDSE/protocol/openid-connect/token";
+// POST
+const LOGOUT_URL: &str = "https://identity.dataspace.copernicus.eu/auth/realms/CDSE/protocol/openid-connect/logout";
 // GET
 // LIST_URL is a template and requires a Collection ID, e.g. SENTINEL-2
 const SEARCH_URL: &str = "https://catalogue.dataspace.copernicus.eu/stac/search";
+// GET
+const COLLECTIONS_URL: &str = "https://catalogue.dataspace.copernicus.eu/stac/collections";
+// Hosts trusted with tokens when downloads are redirected, unless configured otherwise
+pub const DEFAULT_TRUSTED_HOSTS: [&str; 1] = [".dataspace.copernicus.eu"];
+const MAX_REDIRECTS: usize = 10;
+// Pages searched for results matching filters STAC can't apply
+const MAX_FILTERED_PAGES: u16 = 10;
+// Destination reported for products streamed to a writer rather than a file
+pub const STREAM_DESTINATION: &str = "-";
 
 // Core auth struct. Gets saved and updated each run with new information.
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -33,12 +53,17 @@ pub struct AuthDetails {
     pub acquired_time: i64, // When authentication was acquired, to check current age
     pub access_token: String,
     pub expires_in: i32,
+    // Service accounts don't get a refresh token or session, so these may be empty
+    #[serde(default)]
     pub refresh_token: String,
+    #[serde(default)]
     pub refresh_expires_in: i64,
     pub token_type: String,
-    #[serde(rename(serialize = "not-before-policy", deserialize = "not-before-policy"))]
+    #[serde(rename(serialize = "not-before-policy", deserialize = "not-before-policy"), default)]
     pub not_before_policy: i32,
+    #[serde(default)]
     pub session_state: String,
+    #[serde(default)]
     pub scope: String
 }
 
@@ -61,6 +86,8 @@ fn get_auth_state(auth_details: &AuthDetails) -> Result<AuthState, Box<dyn Error
     let is_refresh_expired = now > (auth_details.acquired_time + auth_details.refresh_expires_in).try_into()?;
     match (is_expired, is_refresh_expired) {
         (false, false) => Ok(AuthState::IsOK),
+        // Without a refresh token there's nothing to outlive the access token
+        (false, true) if auth_details.refresh_token.is_empty() => Ok(AuthState::IsOK),
         (true, false) => Ok(AuthState::NeedsRefresh),
         (true, true) => Ok(AuthState::NeedsReauthentication),
         // Auth is in some other state and we should probably reauth
@@ -68,6 +95,17 @@ fn get_auth_state(auth_details: &AuthDetails) -> Result<AuthState, Box<dyn Error
     }
 }
 
+/*
+ * Whether getting working auth will mean logging in from scratch, i.e. whether
+ * the caller has to come up with credentials first.
+ */
+pub fn needs_credentials(auth_details: &Option<AuthDetails>) -> bool {
+    match auth_details {
+        None => true,
+        Some(auth_details) => !matches!(get_auth_state(auth_details), Ok(AuthState::IsOK | AuthState::NeedsRefresh)),
+    }
+}
+
 /*
  * Checks the auth object and does whatever's necessary to get a working auth value.
  */
@@ -114,25 +152,41 @@ async fn authenticate(form_body: &HashMap<&str, String>) -> Result<AuthDetails,
         auth_details.acquired_time = Utc::now().timestamp();
         Ok(auth_details)
     } else {
-        // Debug ok here, since this is effectively a stop error
-        Err(format!("authentication response was abnormal: {response:?}").into())
+        // Keycloak explains rejected logins, e.g. a missing or wrong one-time code,
+        // in the body, which is more use than the response itself.
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        let reason = serde_json::from_str::<serde_json::Value>(&body).ok()
+            .and_then(|v| v.get("error_description").and_then(|d| d.as_str()).map(String::from))
+            .unwrap_or(body);
+        Err(format!("authentication response was abnormal ({status}): {reason}").into())
     }
 }
 
 /*
- * Credentials are required for a new auth object.
+ * Credentials are required for a new auth object. User accounts log in with the
+ * password grant, through the public client, and service accounts with their own
+ * client id and secret.
  */
 pub async fn authenticate_credentials(credentials: &Credentials) -> Result<AuthDetails, Box<dyn Error>> {
-    let form_body = if let (Some(user), Some(pass)) = (credentials.user.clone(), credentials.pass.clone()) {
-        HashMap::from([
-            ("client_id", String::from("cdse-public")),
+    let mut form_body = match (credentials.grant, credentials.user.clone(), credentials.client_id.clone(), credentials.pass.clone()) {
+        (Grant::Password, Some(user), _, Some(pass)) => HashMap::from([
+            ("client_id", String::from(PUBLIC_CLIENT_ID)),
             ("grant_type", String::from("password")),
             ("username", user),
             ("password", pass)
-        ])
-    } else {
-        HashMap::new()
+        ]),
+        (Grant::ClientCredentials, _, Some(client_id), Some(secret)) => HashMap::from([
+            ("client_id", client_id),
+            ("client_secret", secret),
+            ("grant_type", String::from("client_credentials")),
+        ]),
+        (Grant::Password, _, _, _) => return Err("Unable to authenticate: missing user or password.".into()),
+        (Grant::ClientCredentials, _, _, _) => return Err("Unable to authenticate: missing client id or secret.".into()),
     };
+    if let Some(totp) = &credentials.totp {
+        form_body.insert("totp", totp.clone());
+    }
     authenticate(&form_body).await
 }
 
@@ -141,19 +195,37 @@ pub async fn authenticate_credentials(credentials: &Credentials) -> Result<AuthD
  */
 pub async fn refresh_authentication(auth_details: &AuthDetails) -> Result<AuthDetails, Box<dyn Error>> {
     let form_body = HashMap::from([
-        ("client_id", String::from("cdse-public")),
+        ("client_id", String::from(PUBLIC_CLIENT_ID)),
         ("grant_type", String::from("refresh_token")),
         ("refresh_token", auth_details.refresh_token.clone()),
     ]);
     authenticate(&form_body).await
 }
 
+/*
+ * Ends the session behind a refresh token, so a copy of it left anywhere else
+ * stops working too.
+ */
+pub async fn revoke_authentication(auth_details: &AuthDetails) -> Result<(), Box<dyn Error>> {
+    let form_body = HashMap::from([
+        ("client_id", String::from(PUBLIC_CLIENT_ID)),
+        ("refresh_token", auth_details.refresh_token.clone()),
+    ]);
+    let client = reqwest::Client::new();
+    let response: Response = client.post(LOGOUT_URL).form(&form_body).send().await?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("logout response was abnormal: {}", response.status()).into())
+    }
+}
+
 // API Interactions
 
 /*
  * Params for the search endpoints: List, Search
  */
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct QueryParams {
     pub ids: Option<String>,
     pub collections: Option<String>,
@@ -163,6 +235,17 @@ pub struct QueryParams {
     pub sortby: Option<String>,
     pub limit: Option<u16>,
     pub page: Option<u16>,
+    pub backend: Backend,
+    // Filters only the OData catalogue has
+    pub name: Option<String>,
+    pub online: bool,
+    pub attributes: Vec<(String, String)>,
+    pub published_since: Option<DateTime<Utc>>,
+    // Filters STAC can't apply, so these are applied to its results, see search_imagery.
+    // OData filters by them.
+    pub tiles: Vec<String>,
+    pub relative_orbits: Vec<u16>,
+    pub sentinel1: Sentinel1Args,
 }
 
 /*
@@ -170,14 +253,27 @@ pub struct QueryParams {
  */
 impl From<SearchArgs> for QueryParams {
     fn from(a: SearchArgs) -> Self {
-        let SearchArgs { ids, collections, bbox, from, to, sortby, limit, page, .. } = a;
-        QueryParams { ids, collections, bbox, from, to, sortby, limit, page }
-    }
-}
-
-impl From<DownloadArgs> for QueryParams {
-    fn from(da: DownloadArgs) -> Self {
-        QueryParams { ids: da.ids, ..Default::default() }
+        let SearchArgs {
+            ids, collections, bbox, tiles, relative_orbits, sentinel1, from, to, interval, sortby, limit, page, backend, name, online, attributes,
+            published_since, ..
+        } = a;
+        let (from, to) = match interval {
+            Some(interval) => (Some(interval.from), Some(interval.to)),
+            None => (from, to),
+        };
+        // Options only OData has pick it, unless a backend is given. So do Sentinel-1
+        // options, which only OData can filter by.
+        let odata_only = name.is_some() || online || !attributes.is_empty() || published_since.is_some();
+        let backend = backend.unwrap_or(if odata_only || !sentinel1.is_empty() { Backend::Odata } else { Backend::Stac });
+        // The last run is looked up before searching, see RunLog
+        let published_since = match published_since {
+            Some(PublishedSince::Date(date)) => Some(date),
+            _ => None,
+        };
+        QueryParams {
+            ids, collections, bbox, from, to, sortby, limit, page, backend, name, online, attributes, published_since, tiles, relative_orbits,
+            sentinel1
+        }
     }
 }
 
@@ -249,37 +345,81 @@ fn get_header_info(r: &Response) -> (Option<usize>, Option<String>) {
         None
     };
 
-    // Get header value, convert to strings, don't bother parsing them yet.
-    let disposition_file = if let Some(v) = h.get("content-disposition") {
-        if let Ok(dv) = v.to_str() {
-            Some(String::from(dv).split("filename=").last().unwrap_or("").to_string())
-        } else {
-            None
-        }
-    } else {
-        None
-    };
+    // Get header value, then the file name the server suggests, if it's usable.
+    let disposition_file = h.get("content-disposition")
+        .and_then(|v| v.to_str().ok())
+        .and_then(disposition_filename);
 
     (length, disposition_file)
 }
 
-/*
- * Composes a path and output file for downloads.
- */
-fn compose_path(opt_path: Option<String>, name: &String) -> PathBuf {
-    if let Some(path) = opt_path {
-        [&path, &format!("{name}.zip")].iter().collect()
-    } else {
-        ["./", &format!("{name}.zip")].iter().collect()
-    }
+// Full size from a 416 response's content-range, e.g. bytes */1048576
+fn content_range_total(r: &Response) -> Option<u64> {
+    r.headers().get(CONTENT_RANGE)?.to_str().ok()?.strip_prefix("bytes */")?.trim().parse().ok()
+}
+
+// Where a download is written until it's complete, e.g. <id>.zip.part
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
 }
 
-// Queries for imagery that satisfies constraints
+/*
+ * Queries for imagery that satisfies constraints, from the STAC or OData
+ * catalogue, which both give the same kind of features. The catalogue is public,
+ * so this doesn't need auth. STAC can't filter by tile, relative orbit or
+ * Sentinel-1 options, so those are applied to its results, fetching more pages
+ * until there are enough that match.
+ */
 pub async fn search_imagery(
     client: &Client,
-    auth_details: &AuthDetails,
     query_params: QueryParams,
 ) -> Result<FeatureCollection, Box<dyn Error>> {
+    let matches = |f: &Feature| matches_filters(f, &query_params.tiles, &query_params.relative_orbits) && query_params.sentinel1.matches(f);
+    // OData filters by all of these already, so this only catches anything it missed
+    if query_params.backend == Backend::Odata {
+        let mut fc = search_products(client, &query_params).await?;
+        fc.features.retain(matches);
+        return Ok(fc);
+    }
+    let filtered = !query_params.tiles.is_empty() || !query_params.relative_orbits.is_empty() || !query_params.sentinel1.is_empty();
+    let mut fc = search_stac(client, query_params.clone()).await?;
+    if !filtered {
+        return Ok(fc);
+    }
+
+    // Filtering leaves pages short, so later pages make up the limit, or the
+    // catalogue's page size without one, within reason
+    let page_size = query_params.limit.map_or(fc.features.len(), usize::from);
+    let first_page = query_params.page.unwrap_or(1);
+    let mut fetched = fc.features.len();
+    fc.features.retain(matches);
+    let mut page = first_page;
+    while fetched == page_size && page_size > 0 && fc.features.len() < page_size {
+        if page - first_page + 1 >= MAX_FILTERED_PAGES {
+            progress::message(&format!(
+                "Only {} results matched --tile, --relative-orbit or Sentinel-1 options in the first {MAX_FILTERED_PAGES} pages. \
+                 Narrow the search, e.g. by date, to find more.",
+                fc.features.len()
+            ));
+            break;
+        }
+        page += 1;
+        let next = search_stac(client, QueryParams { page: Some(page), limit: Some(page_size as u16), ..query_params.clone() }).await?;
+        fetched = next.features.len();
+        fc.features.extend(next.features.into_iter().filter(matches));
+    }
+    fc.features.truncate(page_size);
+    Ok(fc)
+}
+
+async fn search_stac(client: &Client, query_params: QueryParams) -> Result<FeatureCollection, Box<dyn Error>> {
+    let odata_only = query_params.name.is_some() || query_params.online || !query_params.attributes.is_empty()
+        || query_params.published_since.is_some();
+    if odata_only {
+        return Err("--name, --online, --attribute and --published-since need --backend odata".into());
+    }
     let mut url: Url = Url::parse(SEARCH_URL)?;
     let query_params = generate_query(query_params, true);
     url.set_query(query_params.as_deref());
@@ -287,12 +427,138 @@ pub async fn search_imagery(
     info!("API::list_imagery: Requesting {url}...");
     let response_text = client
         .get(url)
-        .header("Authorization", format!("Bearer {}", auth_details.access_token))
-        .send().await.unwrap().text().await.unwrap_or(String::from("{}"));
+        .send().await?.text().await.unwrap_or(String::from("{}"));
     info!("API::list_imagery: Response: \n{response_text}");
     let geojson = response_text.parse::<GeoJson>()?;
-    let fc: FeatureCollection = FeatureCollection::try_from(geojson)?;
-    Ok(fc)
+    Ok(FeatureCollection::try_from(geojson)?)
+}
+
+/*
+ * Checks a feature's tileId and relativeOrbitNumber against the requested ones.
+ * Empty filters match everything.
+ */
+fn matches_filters(feature: &Feature, tiles: &[String], relative_orbits: &[u16]) -> bool {
+    let property = |name: &str| feature.properties.as_ref().and_then(|p| p.get(name).cloned());
+    let tile_ok = tiles.is_empty() || property("tileId")
+        .and_then(|v| v.as_str().map(String::from))
+        .is_some_and(|tile| tiles.contains(&tile));
+    let orbit_ok = relative_orbits.is_empty() || property("relativeOrbitNumber")
+        .and_then(|v| v.as_u64())
+        .is_some_and(|orbit| relative_orbits.iter().any(|&o| o as u64 == orbit));
+    tile_ok && orbit_ok
+}
+
+/*
+ * Collection summary, from the STAC collections endpoint. Collections carry a lot
+ * more, e.g. extents and summaries, but this is what's useful to pick one.
+ */
+#[derive(Deserialize, Debug)]
+pub struct Collection {
+    pub id: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CollectionsResponse {
+    collections: Vec<Collection>,
+}
+
+// Lists the collections that can be passed to search
+pub async fn list_collections(client: &Client) -> Result<Vec<Collection>, Box<dyn Error>> {
+    info!("API::list_collections: Requesting {COLLECTIONS_URL}...");
+    let response = client.get(COLLECTIONS_URL).send().await?;
+    if !response.status().is_success() {
+        return Err(format!("Failure response from server: {}", response.status()).into());
+    }
+    let response_text = response.text().await?;
+    debug!("API::list_collections: Response: \n{response_text}");
+    let collections: CollectionsResponse = serde_json::from_str(&response_text)?;
+    Ok(collections.collections)
+}
+
+/*
+ * Looks up a product's size before downloading it. OData has the product's
+ * ContentLength, and the product href is its OData entry plus /$value, so that's
+ * tried first. Otherwise a HEAD request for the product might have a
+ * content-length. Neither needs auth.
+ */
+pub async fn fetch_product_size(client: &Client, feature: &Feature) -> Option<u64> {
+    let href = get_value(from_path(Vec::from(["assets", "PRODUCT", "href"]), &feature.foreign_members))?;
+    if let Some(entry_url) = href.strip_suffix("/$value") {
+        info!("API::fetch_product_size: Requesting {entry_url}...");
+        if let Ok(response) = client.get(entry_url).send().await {
+            if response.status().is_success() {
+                let body = response.text().await.unwrap_or_default();
+                let entry: serde_json::Value = serde_json::from_str(&body).unwrap_or_default();
+                if let Some(length) = entry.get("ContentLength").and_then(|v| v.as_u64()) {
+                    return Some(length);
+                }
+            }
+        }
+    }
+    debug!("API::fetch_product_size: No OData size, trying HEAD {href}");
+    let response = client.head(&href).send().await.ok()?;
+    if response.status().is_success() {
+        get_header_info(&response).0.map(|length| length as u64)
+    } else {
+        None
+    }
+}
+
+/*
+ * Client for downloads, which leaves redirects to get_following_redirects.
+ */
+pub fn download_client() -> Result<Client, Box<dyn Error>> {
+    Ok(Client::builder().redirect(Policy::none()).build()?)
+}
+
+/*
+ * Whether a URL's host can be sent a token: over https, to a host in the list,
+ * or under a domain in it given with a leading dot, e.g. .dataspace.copernicus.eu.
+ */
+fn is_trusted_host(url: &Url, trusted_hosts: &[String]) -> bool {
+    let host = if let Some(host) = url.host_str() { host.to_lowercase() } else { return false };
+    url.scheme() == "https" && trusted_hosts.iter().map(|t| t.trim().to_lowercase()).any(|trusted| {
+        match trusted.strip_prefix('.') {
+            Some(domain) => host == domain || host.ends_with(&trusted),
+            None => host == trusted,
+        }
+    })
+}
+
+/*
+ * GETs a download, following redirects here rather than in reqwest, which drops
+ * Authorization when a redirect changes host. Product hrefs are on the catalogue
+ * host and redirect to the download one, which needs the token, so it's sent to
+ * every trusted host along the way, and only to those.
+ */
+async fn get_following_redirects(
+    client: &Client, url: Url, token: &str, range: Option<u64>, trusted_hosts: &[String]
+) -> Result<Response, Box<dyn Error>> {
+    let mut url = url;
+    for _ in 0..=MAX_REDIRECTS {
+        let mut request = client.get(url.clone()).timeout(Duration::from_secs(1_000_000));
+        if is_trusted_host(&url, trusted_hosts) {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        } else {
+            debug!("API::get_following_redirects: {} isn't a trusted host, not sending a token", url.host_str().unwrap_or_default());
+        }
+        if let Some(existing) = range {
+            request = request.header(RANGE, format!("bytes={existing}-"));
+        }
+        let response = request.send().await?;
+        if !response.status().is_redirection() {
+            return Ok(response);
+        }
+        let location = response.headers().get(LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .ok_or(format!("Redirect from {url} without a location ({})", response.status()))?;
+        let next = url.join(location)?;
+        debug!("API::get_following_redirects: {} from {url} to {next}", response.status());
+        url = next;
+    }
+    Err(format!("Too many redirects downloading, last to {url}").into())
 }
 
 /*
@@ -315,68 +581,142 @@ pub async fn download_imagery(
     auth_details: &AuthDetails,
     feature: &Feature,
     output_dir: Option<String>,
+    name_template: &Option<String>,
+    limit_rate: Option<u64>,
+    trusted_hosts: &[String],
 ) -> Result<DownloadDetails, Box<dyn Error>> {
-    let mut s = Spinner::new(Spinners::Dots, "Preparing download...".into());
-    let feature_id = get_id(&feature.id);
-    let path = Vec::from(["assets", "PRODUCT", "href"]);
-    let product_url = get_value(from_path(path, &feature.foreign_members));
-    if let (Some(id), Some(catalogue_href)) = (feature_id, product_url) {
-        // This seems to be required by the API. The URI we obtain has the catalogue subdomain, and
-        // when curl'ed or wget'ed the API responds with a 301 redirecting to the download
-        // subdomain, but seemingly returns a 401s for this tool.
-        // The Python example in the official docs begins with a download subdomain url, so it's
-        // not clear whether it's expected that you do string substitution when using the feature's
-        // product URL.
-        let download_url = catalogue_href.replace("catalogue", "download");
-        let url = Url::parse(&download_url)?;
-        let request = client
-            .get(url)
-            .timeout(Duration::from_secs(1_000_000))
-            .header("Authorization", format!("Bearer {}", auth_details.access_token));
-        let response = request.send().await?;
-        // Create file, write byte stream
-        s.stop_with_newline();
-        if response.status().is_success() {
-            print_over("Downloading...");
-            // Fetch some header values to inform our download
-            let (header_length, _header_disposition) = get_header_info(&response);
-            let total_length = header_length.unwrap_or_default();
-            let path = compose_path(output_dir, &id);
-            let mut f = File::create(&path)?;
-            let mut stream = response.bytes_stream();
-
-            let mut bytes_written: usize = 0;
-            loop {
-                if let Some(bytes) = stream.next().await {
-                    match f.write(&bytes?) {
-                        Ok(n) => {
-                            bytes_written += n;
-                            if total_length > 0 {
-                                let percentage = (bytes_written as f64) / (total_length as f64) * 100.0;
-                                print_over(&format!("Downloading. {percentage:.2}%"));
-                            }
-                        },
-                        Err(e) => {
-                            error!("Something went wrong: {e}");
-                            break;
-                        }
-                    }
-                } else {
-                    debug!("write ended.");
-                    break;
-                }
-            }
-            println!("\nDownload complete.");
-            Ok(DownloadDetails {
-                destination: path,
-                size: bytes_written
-            })
-        } else {
-            println!("failed. response:\n{response:#?}");
-            Err(format!("Failure response from server: {response:#?}").into())
+    let task = progress::task("Preparing download...");
+    let (id, url) = product_link(feature)?;
+    debug!("API::download_imagery: Downloading {id} from {url}");
+    // Products are written to a .part file first, and renamed once complete. A
+    // .part file left by an interrupted download is resumed from where it ended.
+    // The server's file name isn't known yet, so this goes by the initial one.
+    let initial = initial_path(feature, &output_dir, name_template)?;
+    let part_path = partial_path(&initial);
+    if let Some(dir) = part_path.parent() {
+        create_dir_all(dir)?;
+    }
+    let existing = metadata(&part_path).map(|m| m.len()).unwrap_or(0);
+    let range = Some(existing).filter(|&e| e > 0);
+    let mut response = get_following_redirects(client, url.clone(), &auth_details.access_token, range, trusted_hosts).await?;
+    // Nothing past the end of the .part file, which can be complete already if we
+    // were stopped before renaming it. Otherwise it's no good, so start over.
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        let total = match content_range_total(&response) {
+            Some(total) => Some(total),
+            None => fetch_product_size(client, feature).await,
+        };
+        if total == Some(existing) {
+            task.finish();
+            debug!("API::download_imagery: {} is complete already", part_path.display());
+            // No file name comes with this, so it's whatever the last one was
+            let path = planned_path(feature, &output_dir, name_template)?;
+            rename(&part_path, &path)?;
+            progress::download_finished(&path, existing);
+            return Ok(DownloadDetails { destination: path, size: existing as usize });
         }
-    } else {
-        Err(format!("Unable to download resource {:?}", feature.id).into())
+        debug!("API::download_imagery: {} doesn't match the product ({total:?} bytes), starting over", part_path.display());
+        response = get_following_redirects(client, url, &auth_details.access_token, None, trusted_hosts).await?;
+    }
+    task.finish();
+    if !response.status().is_success() {
+        return Err(format!("Failure response from server: {response:#?}").into());
+    }
+    // Servers that ignore the range send everything again
+    let resumed = existing > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+    // Templates decide the name themselves, otherwise the server's is used
+    let server_name = get_header_info(&response).1.filter(|_| name_template.is_none());
+    let offset = if resumed { existing as usize } else { 0 };
+    // Create file, write byte stream
+    let mut f = OpenOptions::new().create(true).write(true).append(resumed).truncate(!resumed).open(&part_path).await?;
+    let target = part_path.display().to_string();
+    let (bytes_written, total_length) = write_stream(&id, response, &mut f, &target, offset, limit_rate).await?;
+    if total_length > 0 && bytes_written < total_length {
+        return Err(format!("Download ended early, {bytes_written} of {total_length} bytes. Run it again to resume.").into());
+    }
+    let path = match &server_name {
+        Some(name) => initial.with_file_name(name),
+        None => planned_path(feature, &output_dir, name_template)?,
+    };
+    rename(&part_path, &path)?;
+    if let Some(name) = &server_name {
+        ServerNames::record(&id, name)?;
     }
+    progress::download_finished(&path, bytes_written as u64);
+    Ok(DownloadDetails {
+        destination: path,
+        size: bytes_written,
+    })
 }
 
+/*
+ * Streams the product for a feature into a writer, e.g. stdout or a hashing
+ * process, instead of a file. Nothing touches the disk, so there's no resuming:
+ * a download that ends early is an error, and the writer has a partial product.
+ * Returns the number of bytes written.
+ */
+pub async fn download_to_writer<W: AsyncWrite + Unpin>(
+    client: &Client,
+    auth_details: &AuthDetails,
+    feature: &Feature,
+    writer: &mut W,
+    limit_rate: Option<u64>,
+    trusted_hosts: &[String],
+) -> Result<usize, Box<dyn Error>> {
+    let task = progress::task("Preparing download...");
+    let (id, url) = product_link(feature)?;
+    debug!("API::download_to_writer: Downloading {id} from {url}");
+    let response = get_following_redirects(client, url, &auth_details.access_token, None, trusted_hosts).await?;
+    task.finish();
+    if !response.status().is_success() {
+        return Err(format!("Failure response from server: {response:#?}").into());
+    }
+    let (bytes_written, total_length) = write_stream(&id, response, writer, STREAM_DESTINATION, 0, limit_rate).await?;
+    if total_length > 0 && bytes_written < total_length {
+        return Err(format!("Download ended early, {bytes_written} of {total_length} bytes.").into());
+    }
+    progress::download_finished(Path::new(STREAM_DESTINATION), bytes_written as u64);
+    Ok(bytes_written)
+}
+
+// A feature's id, and where its product is downloaded from
+fn product_link(feature: &Feature) -> Result<(String, Url), Box<dyn Error>> {
+    let path = Vec::from(["assets", "PRODUCT", "href"]);
+    match (get_id(&feature.id), get_value(from_path(path, &feature.foreign_members))) {
+        (Some(id), Some(product_href)) => Ok((id, Url::parse(&product_href)?)),
+        _ => Err(format!("Unable to download resource {:?}", feature.id).into()),
+    }
+}
+
+/*
+ * Writes a product response's body, reporting progress and keeping to the rate
+ * limit. offset is what an earlier, resumed download wrote already, and counts
+ * towards the totals returned: bytes written, and bytes expected, or 0 if the
+ * server didn't say.
+ */
+async fn write_stream<W: AsyncWrite + Unpin>(
+    id: &str, response: Response, writer: &mut W, target: &str, offset: usize, limit_rate: Option<u64>
+) -> Result<(usize, usize), Box<dyn Error>> {
+    let header_length = get_header_info(&response).0;
+    let total_length = header_length.map(|l| l + offset).unwrap_or_default();
+    progress::download_started(id, header_length.map(|l| (l + offset) as u64), offset as u64);
+    let mut stream = response.bytes_stream();
+    let mut limiter = limit_rate.map(RateLimiter::new);
+
+    let mut bytes_written: usize = offset;
+    while let Some(bytes) = stream.next().await {
+        let bytes = bytes?;
+        if let Err(e) = writer.write_all(&bytes).await {
+            error!("Something went wrong: {e}");
+            return Err(format!("Unable to write {target}: {e}").into());
+        }
+        bytes_written += bytes.len();
+        if let Some(limiter) = limiter.as_mut() {
+            limiter.consumed(bytes.len()).await;
+        }
+        progress::download_progress(bytes_written as u64);
+    }
+    writer.flush().await.map_err(|e| format!("Unable to write {target}: {e}"))?;
+    debug!("write ended.");
+    Ok((bytes_written, total_length))
+}
@@ -0,0 +1,204 @@
+use std::error::Error;
+use std::fs::{create_dir_all, File};
+use std::io::{copy, Read};
+use std::path::{Component, Path, PathBuf};
+
+use log::{debug, warn};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use zip::ZipArchive;
+
+/*
+ * Product archives are SAFE directories zipped up, e.g. <id>.SAFE/manifest.safe.
+ * This extracts them and reads their metadata files directly from the archive.
+ */
+
+/*
+ * What `inspect` reports about a product archive.
+ */
+#[derive(Debug, Default)]
+pub struct ProductInfo {
+    pub name: String,
+    pub files: usize,
+    pub granules: Vec<String>,
+    // Band name and resolution in meters, from the product metadata
+    pub bands: Vec<(String, Option<u32>)>,
+    // Measurement files, for products without band metadata, e.g. Sentinel-1
+    pub measurements: Vec<String>,
+    // Ring of [lon, lat] positions
+    pub footprint: Vec<[f64; 2]>,
+}
+
+/*
+ * Where an archive gets extracted to, e.g. products/<id>.SAFE.zip goes to
+ * products/<id>.SAFE.
+ */
+pub fn safe_dir_for(archive: &Path) -> PathBuf {
+    let stem = archive.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let name = if stem.ends_with(".SAFE") { stem } else { format!("{stem}.SAFE") };
+    archive.with_file_name(name)
+}
+
+/*
+ * Extracts an archive into destination, one entry at a time. Entries whose paths
+ * would end up outside destination (zip slip) are refused, and symlinks are
+ * skipped. A leading directory matching destination's name isn't repeated, so
+ * <id>.SAFE/manifest.safe ends up at destination/manifest.safe.
+ */
+pub fn extract_archive(archive: &Path, destination: &Path) -> Result<usize, Box<dyn Error>> {
+    let mut zip = ZipArchive::new(File::open(archive)?)?;
+    let top_level = destination.file_name().map(|s| s.to_string_lossy().to_string());
+    create_dir_all(destination)?;
+
+    let mut extracted: usize = 0;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let enclosed = match entry.enclosed_name() {
+            Some(path) => path,
+            None => return Err(format!("Refusing to extract unsafe path: {}", entry.name()).into())
+        };
+        if entry.is_symlink() {
+            warn!("Skipping symlink in archive: {}", entry.name());
+            continue;
+        }
+        let relative: PathBuf = match enclosed.components().next() {
+            Some(Component::Normal(first)) if Some(first.to_string_lossy().to_string()) == top_level => {
+                enclosed.components().skip(1).collect()
+            },
+            _ => enclosed,
+        };
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        let path = destination.join(&relative);
+        if entry.is_dir() {
+            create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        debug!("Extracting {}", relative.display());
+        let mut f = File::create(&path)?;
+        copy(&mut entry, &mut f)?;
+        extracted += 1;
+    }
+    Ok(extracted)
+}
+
+/*
+ * Reads an archive's manifest and product metadata (MTD_*.xml) without
+ * extracting it.
+ */
+pub fn inspect_archive(archive: &Path) -> Result<ProductInfo, Box<dyn Error>> {
+    let mut zip = ZipArchive::new(File::open(archive)?)?;
+    let names: Vec<String> = zip.file_names().map(String::from).collect();
+    let file_name = |n: &String| n.rsplit('/').next().unwrap_or_default().to_string();
+
+    let mut info = ProductInfo {
+        name: names.iter()
+            .find_map(|n| n.split('/').next().filter(|first| first.ends_with(".SAFE")).map(String::from))
+            .unwrap_or(archive.file_stem().unwrap_or_default().to_string_lossy().to_string()),
+        files: names.iter().filter(|n| !n.ends_with('/')).count(),
+        ..Default::default()
+    };
+
+    // Granules are directories under GRANULE/
+    for name in &names {
+        let parts: Vec<&str> = name.split('/').collect();
+        if let Some(i) = parts.iter().position(|&p| p == "GRANULE") {
+            if let Some(granule) = parts.get(i + 1).filter(|g| !g.is_empty()) {
+                if !info.granules.contains(&granule.to_string()) {
+                    info.granules.push(granule.to_string());
+                }
+            }
+        }
+    }
+
+    info.measurements = names.iter()
+        .filter(|n| n.contains("/measurement/") && !n.ends_with('/'))
+        .map(file_name)
+        .collect();
+
+    // Product metadata has bands and the footprint for Sentinel-2
+    if let Some(mtd) = names.iter().find(|n| file_name(n).starts_with("MTD_MSIL") && n.ends_with(".xml")) {
+        let xml = read_entry(&mut zip, mtd)?;
+        parse_product_metadata(&xml, &mut info)?;
+    }
+
+    // Otherwise the manifest has a footprint
+    if info.footprint.is_empty() {
+        if let Some(manifest) = names.iter().find(|n| file_name(n) == "manifest.safe") {
+            let xml = read_entry(&mut zip, manifest)?;
+            info.footprint = parse_manifest_footprint(&xml)?;
+        }
+    }
+    Ok(info)
+}
+
+fn read_entry(zip: &mut ZipArchive<File>, name: &str) -> Result<String, Box<dyn Error>> {
+    let mut contents = String::new();
+    zip.by_name(name)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/*
+ * Spectral_Information elements give each band and its resolution, and the
+ * footprint is in EXT_POS_LIST as "lat lon lat lon ...".
+ */
+fn parse_product_metadata(xml: &str, info: &mut ProductInfo) -> Result<(), Box<dyn Error>> {
+    let mut reader = Reader::from_str(xml);
+    let mut current: Vec<u8> = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                current = e.local_name().as_ref().to_vec();
+                if current == b"Spectral_Information" {
+                    if let Some(band) = e.try_get_attribute("physicalBand")? {
+                        info.bands.push((band.unescape_value()?.to_string(), None));
+                    }
+                }
+            },
+            Event::Text(t) => {
+                let text = t.unescape()?;
+                match current.as_slice() {
+                    b"RESOLUTION" => if let Some(band) = info.bands.last_mut() {
+                        band.1 = text.trim().parse().ok();
+                    },
+                    b"EXT_POS_LIST" => {
+                        let values: Vec<f64> = text.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+                        info.footprint = values.chunks_exact(2).map(|c| [c[1], c[0]]).collect();
+                    },
+                    _ => ()
+                }
+            },
+            Event::End(_) => current.clear(),
+            Event::Eof => break,
+            _ => ()
+        }
+    }
+    Ok(())
+}
+
+/*
+ * The manifest footprint is gml:coordinates as "lat,lon lat,lon ...".
+ */
+fn parse_manifest_footprint(xml: &str) -> Result<Vec<[f64; 2]>, Box<dyn Error>> {
+    let mut reader = Reader::from_str(xml);
+    let mut in_coordinates = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => in_coordinates = e.local_name().as_ref() == b"coordinates",
+            Event::Text(t) if in_coordinates => {
+                return Ok(t.unescape()?.split_whitespace()
+                    .filter_map(|pair| pair.split_once(','))
+                    .filter_map(|(lat, lon)| Some([lon.parse().ok()?, lat.parse().ok()?]))
+                    .collect());
+            },
+            Event::End(_) => in_coordinates = false,
+            Event::Eof => break,
+            _ => ()
+        }
+    }
+    Ok(Vec::new())
+}
@@ -3,22 +3,64 @@ use std::default::Default;
 
 use chrono::offset::Utc;
 use chrono::DateTime;
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
-use crate::util::parse_date;
+use crate::dates::{DateRange, Span, parse_interval, parse_month_span, parse_year_span};
+use crate::sentinel1::Sentinel1Args;
+use crate::throttle::{TimeWindow, parse_rate, parse_time_window};
+use crate::tiles::parse_tile_name;
+use crate::util::{parse_bbox, parse_date};
 
 
 #[derive(Clone, Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct CliArgs {
+    #[arg(long, global = true, help = "named account from the config file to authenticate with")]
+    pub profile: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value = "auto",
+        help = "when to color output. auto colors a terminal, unless NO_COLOR is set"
+    )]
+    pub color: ColorChoice,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value = "auto",
+        help = "how to show progress, on stderr. auto shows bars on a terminal and plain lines otherwise. json writes an event per line"
+    )]
+    pub progress: ProgressStyle,
+    #[arg(short, long, global = true, help = "don't show progress, same as --progress none")]
+    pub quiet: bool,
     #[command(subcommand)]
     pub mode: Mode,
 }
 
 #[derive(Clone, Debug, Subcommand)]
 pub enum Mode {
-    Search(SearchArgs),
+    Search(SearchCommandArgs),
+    #[command(about = "search, then keep the best scene per tile for each period")]
+    Select(SelectArgs),
+    #[command(about = "search, then browse results full screen and pick products to queue for download")]
+    Browse(BrowseArgs),
+    #[command(about = "list the collections available to search")]
+    Collections,
+    #[command(about = "list the Sentinel-2 tiles covering a bounding box")]
+    Tiles(TilesArgs),
+    #[command(about = "search, then write the footprints of results as GeoJSON or KML")]
+    ExportFootprints(ExportArgs),
     Download(DownloadArgs),
+    #[command(about = "queue downloads, and run them in a way that survives interruptions")]
+    Queue(QueueArgs),
+    #[command(about = "list the bands, granules and footprint of a downloaded product")]
+    Inspect(InspectArgs),
+    #[command(about = "store or remove the password for --profile")]
+    Credentials(CredentialsArgs),
+    #[command(about = "show, acquire or remove stored auth tokens")]
+    Auth(AuthArgs),
 }
 
 
@@ -30,43 +72,332 @@ pub struct SearchArgs {
     pub collections: Option<String>,
     #[arg(long, help = "provides a bounding box for the query(top left, bottom right)")]
     pub bbox: Option<String>,
+    #[arg(
+        long = "tile",
+        value_delimiter = ',',
+        value_parser = parse_tile_name,
+        help = "Sentinel-2 tiles to search, e.g. 18TVK,18TWK. Used for the bbox if none is given"
+    )]
+    pub tiles: Vec<String>,
+    #[arg(long = "relative-orbit", value_delimiter = ',', help = "relative orbit numbers to keep, e.g. 54")]
+    pub relative_orbits: Vec<u16>,
+    #[command(flatten)]
+    pub sentinel1: Sentinel1Args,
+    #[arg(long, help = "GeoJSON file with the area of interest. Used for the bbox if none is given")]
+    pub aoi: Option<String>,
+    #[arg(
+        long = "min-coverage",
+        value_parser = parse_percent,
+        help = "drop results covering less than this percent of the area of interest"
+    )]
+    pub min_coverage: Option<f64>,
+    #[arg(long, help = "report how much of the area of interest the results cover, and any gaps")]
+    pub coverage: bool,
     #[arg(
         long,
-        help = "start of range to query by: YYYY-MM-DDTHH:MM:SSZ or YYYY-MM-DD",
+        help = "start of range to query by: YYYY-MM-DDTHH:MM:SSZ, YYYY-MM-DD, or relative, e.g. 7d, 2w, P30D, yesterday, last-month",
         value_parser = |s: &str| parse_datetime(s, Some(TimeAdjust::Floor))
     )]
     pub from: Option<DateTime<Utc>>,
     #[arg(
         long,
-        help = "end of range to query by: YYYY-MM-DDTHH:MM:SSZ or YYYY-MM-DD",
+        help = "end of range to query by: YYYY-MM-DDTHH:MM:SSZ, YYYY-MM-DD, or relative, e.g. today, last-month",
         value_parser = |s: &str| parse_datetime(s, Some(TimeAdjust::Ceil))
     )]
     pub to: Option<DateTime<Utc>>,
+    #[arg(
+        long,
+        conflicts_with_all = ["from", "to"],
+        value_parser = parse_interval,
+        help = "ISO 8601 interval to query by, e.g. 2024-06-01/P30D, P2W/2024-07-01 or just P7D, ending now"
+    )]
+    pub interval: Option<DateRange>,
+    #[arg(long, value_parser = parse_month_span, help = "months to search each year, e.g. 6-8 for June to August, or 12-2 across the new year")]
+    pub months: Option<Span>,
+    #[arg(long, value_parser = parse_year_span, help = "years to search --months in, e.g. 2019-2024. Defaults to those in --from/--to")]
+    pub years: Option<Span>,
+    #[arg(
+        long,
+        value_enum,
+        help = "catalogue API to search. Defaults to stac, or odata for the options only it has or filters by, e.g. --name or --polarisation"
+    )]
+    pub backend: Option<Backend>,
+    #[arg(long, help = "only products whose name contains this, e.g. _T18TVK_ or MSIL2A. Needs --backend odata")]
+    pub name: Option<String>,
+    #[arg(long, help = "only products that can be downloaded right away, not archived ones. Needs --backend odata")]
+    pub online: bool,
+    #[arg(
+        long = "attribute",
+        value_parser = parse_attribute,
+        help = "only products with this attribute value, e.g. cloudCover=0 or productType=S2MSI2A. Needs --backend odata"
+    )]
+    pub attributes: Vec<(String, String)>,
+    #[arg(
+        long = "published-since",
+        value_parser = parse_published_since,
+        help = "only products added to the catalogue since a date, or since the last run of the same search with \"last\". Uses --backend odata"
+    )]
+    pub published_since: Option<PublishedSince>,
     #[arg(long, help = "sort query results by direction, field. [+|-][start_datetime | end_datetime | datetime]")]
     pub sortby: Option<String>,
     #[arg(long, help = "which page to fetch for paginated responses")]
     pub page: Option<u16>,
     #[arg(long, help = "limit on the number of items returned")]
     pub limit: Option<u16>,
+    #[arg(long, help = "show results as a table, one row per feature")]
+    pub table: bool,
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "id,date,cloud,tile,level",
+        help = "columns to show with --table. Only OData results have sizes"
+    )]
+    pub columns: Vec<Column>,
+    #[arg(long = "sort-by", help = "sort results after fetching them by a property or column, e.g. cloud or -datetime")]
+    pub sort_by: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum Column {
+    Id,
+    Date,
+    Cloud,
+    Tile,
+    Level,
+    Size,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct TilesArgs {
+    #[arg(long, value_parser = parse_bbox, help = "bounding box to find tiles for(top left, bottom right)")]
+    pub bbox: [f64; 4],
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct SearchCommandArgs {
+    #[command(flatten)]
+    pub search: SearchArgs,
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["table", "coverage"],
+        help = "print results for other commands to read, e.g. download --ids-from -, instead of listing them"
+    )]
+    pub format: Option<ResultFormat>,
+}
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum ResultFormat {
+    // One FeatureCollection
+    Geojson,
+    // A feature per line
+    Ndjson,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct SelectArgs {
+    #[command(flatten)]
+    pub search: SearchArgs,
+    #[arg(long, value_enum, default_value_t = Period::Month, help = "length of the windows to pick a scene from")]
+    pub per: Period,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct BrowseArgs {
+    #[command(flatten)]
+    pub search: SearchArgs,
+    #[arg(short = 'o', long = "output", help = "Where to write files picked for download")]
+    pub output_dir: Option<String>,
+    #[arg(
+        long = "name-template",
+        help = "where to put files under the output dir, e.g. {collection}/{date:%Y/%m}/{tileId}/{id}.zip. Defaults to the server's file name"
+    )]
+    pub name_template: Option<String>,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub search: SearchArgs,
+    #[arg(long, value_enum, default_value_t = FootprintFormat::Geojson, help = "file format to write")]
+    pub format: FootprintFormat,
+    #[arg(short = 'o', long = "output", help = "file to write, instead of stdout")]
+    pub output: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum FootprintFormat {
+    Geojson,
+    Kml,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, ValueEnum)]
+pub enum Backend {
+    #[default]
+    Stac,
+    Odata,
+}
+
+// A date, or the last time the same search ran
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PublishedSince {
+    Date(DateTime<Utc>),
+    LastRun,
+}
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum ProgressStyle {
+    Auto,
+    Bar,
+    Plain,
+    Json,
+    None,
 }
 
 #[derive(Clone, Debug, Default, Args)]
 pub struct DownloadArgs {
-    #[arg(long = "ids")]
+    #[arg(help = "results of the last search to download, by their number in it, e.g. 1 4 7")]
+    pub results: Vec<usize>,
+    #[arg(long, help = "download from the results of the last search, instead of searching again")]
+    pub last: bool,
+    #[arg(long, requires = "last", help = "with --last, download every result")]
+    pub all: bool,
+    #[arg(long = "ids", conflicts_with_all = ["results", "last"])]
     pub ids: Option<String>,
+    #[arg(
+        long = "ids-from",
+        conflicts_with_all = ["results", "last"],
+        help = "file, or - for stdin, listing products to download: ids, a GeoJSON FeatureCollection or search --format ndjson output"
+    )]
+    pub ids_from: Option<String>,
+    #[arg(short = 'o', long = "output", help = "Where to write files, or - to write a single product to stdout")]
+    pub output_dir: Option<String>,
+    #[arg(
+        long = "name-template",
+        help = "where to put files under the output dir, e.g. {collection}/{date:%Y/%m}/{tileId}/{id}.zip. Defaults to the server's file name"
+    )]
+    pub name_template: Option<String>,
+    #[arg(long, help = "unpack the downloaded archive into <id>.SAFE/")]
+    pub extract: bool,
+    #[arg(long = "dry-run", help = "list what would be downloaded and its total size, without downloading")]
+    pub dry_run: bool,
+    #[command(flatten)]
+    pub throttle: ThrottleArgs,
+}
+
+#[derive(Clone, Debug, Default, Args)]
+pub struct ThrottleArgs {
+    #[arg(long = "limit-rate", value_parser = parse_rate, help = "maximum download speed in bytes per second, e.g. 500K or 20M")]
+    pub limit_rate: Option<u64>,
+    #[arg(
+        long,
+        value_parser = parse_time_window,
+        help = "only start downloads between these local times, e.g. 22:00-06:00, waiting until then if needed"
+    )]
+    pub window: Option<TimeWindow>,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct QueueArgs {
+    #[command(subcommand)]
+    pub action: QueueAction,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum QueueAction {
+    #[command(about = "queue products by id, or everything a search finds")]
+    Add(Box<QueueAddArgs>),
+    #[command(about = "download everything pending, resuming interrupted downloads")]
+    Run(QueueRunArgs),
+    #[command(about = "list queued products and how their downloads went")]
+    Status,
+    #[command(about = "remove finished downloads from the queue")]
+    Clear {
+        #[arg(long, help = "remove everything, not just finished downloads")]
+        all: bool,
+    },
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct QueueAddArgs {
+    #[arg(help = "product ids to queue. Without any, the search options pick products")]
+    pub products: Vec<String>,
+    #[command(flatten)]
+    pub search: SearchArgs,
     #[arg(short = 'o', long = "output", help = "Where to write files")]
     pub output_dir: Option<String>,
+    #[arg(
+        long = "name-template",
+        help = "where to put files under the output dir, e.g. {collection}/{date:%Y/%m}/{tileId}/{id}.zip. Defaults to the server's file name"
+    )]
+    pub name_template: Option<String>,
 }
 
-/*
- * impl to make converting from passed args to search params easy.
- */
-impl From<DownloadArgs> for SearchArgs {
-    fn from(da: DownloadArgs) -> Self {
-        SearchArgs { ids: da.ids, ..Default::default() }
-    }
+#[derive(Clone, Debug, Args)]
+pub struct QueueRunArgs {
+    #[arg(long = "retry-failed", help = "try failed downloads again too")]
+    pub retry_failed: bool,
+    #[command(flatten)]
+    pub throttle: ThrottleArgs,
 }
 
+#[derive(Clone, Debug, Args)]
+pub struct InspectArgs {
+    #[arg(help = "downloaded product archive, e.g. <id>.zip")]
+    pub archive: String,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct CredentialsArgs {
+    #[command(subcommand)]
+    pub action: CredentialsAction,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum CredentialsAction {
+    #[command(about = "prompt for a password and save it to the keyring or credentials file")]
+    Set {
+        #[arg(long = "allow-file", help = "save to the credentials file if the keyring isn't available")]
+        allow_file: bool,
+    },
+    #[command(about = "remove a saved password")]
+    Remove,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct AuthArgs {
+    #[command(subcommand)]
+    pub action: AuthAction,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum AuthAction {
+    #[command(about = "list stored tokens and when they expire")]
+    Status,
+    #[command(about = "authenticate with credentials and store new tokens")]
+    Login,
+    #[command(about = "end the session and wipe stored tokens")]
+    Logout {
+        #[arg(long, help = "wipe tokens for every profile, not just the current one")]
+        all: bool,
+    },
+}
 
 pub enum TimeAdjust {
     Floor,
@@ -84,3 +415,27 @@ fn parse_datetime(datetime_str: &str, should_adjust: Option<TimeAdjust>) -> Resu
     }
 }
 
+
+// Percentages from 0 to 100, e.g. for --min-coverage 80
+fn parse_percent(s: &str) -> Result<f64, Box<dyn Error + Send + Sync>> {
+    match s.trim().trim_end_matches('%').parse::<f64>() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(p),
+        _ => Err(format!("Not a percentage from 0 to 100: {s}").into()),
+    }
+}
+
+// Product attribute filters, e.g. cloudCover=0
+fn parse_attribute(s: &str) -> Result<(String, String), Box<dyn Error + Send + Sync>> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("Attributes are given as name=value: {s}").into()),
+    }
+}
+
+fn parse_published_since(s: &str) -> Result<PublishedSince, Box<dyn Error + Send + Sync>> {
+    if s.trim().eq_ignore_ascii_case("last") {
+        Ok(PublishedSince::LastRun)
+    } else {
+        parse_date(s, Some(TimeAdjust::Floor)).map(PublishedSince::Date)
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashSet;
+use std::error::Error;
+
+use geojson::{Feature, FeatureCollection, Value as GeometryValue};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::symbols::Marker;
+use ratatui::text::{Line, Span};
+use ratatui::widgets::canvas::{Canvas, Line as CanvasLine, Map, MapResolution};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::Frame;
+
+use crate::args::Column;
+use crate::table::{available_columns, column_value, header, product_size, sort_features};
+use crate::util::{collection_of, format_size, get_id, get_value};
+
+// Rows moved by page up and down
+const PAGE: usize = 10;
+// Degrees shown around a footprint, so there's some map to place it by
+const MAP_MARGIN: f64 = 1.0;
+
+const KEYS: &str =
+    "↑↓ move  space select  a all  s sort  r reverse  J/K scroll details  enter queue  q quit";
+
+/*
+ * Full screen browser for search results: a table of features, with the details
+ * and footprint of the highlighted one alongside. Features picked with space are
+ * returned to be queued on enter, or the highlighted one if none are. Quitting
+ * returns nothing.
+ */
+pub fn browse(fc: FeatureCollection, columns: &[Column]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut browser = Browser::new(fc, columns);
+    let mut terminal = ratatui::init();
+    let result = browser.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+struct Browser {
+    fc: FeatureCollection,
+    columns: Vec<Column>,
+    state: TableState,
+    // Ids picked for queueing
+    picked: HashSet<String>,
+    // Column sorted by, and whether descending
+    sort: Option<(usize, bool)>,
+    detail_scroll: u16,
+}
+
+impl Browser {
+    fn new(fc: FeatureCollection, columns: &[Column]) -> Self {
+        let mut state = TableState::default();
+        state.select(if fc.features.is_empty() { None } else { Some(0) });
+        let columns = available_columns(&fc, columns);
+        Browser { fc, columns, state, picked: HashSet::new(), sort: None, detail_scroll: 0 }
+    }
+
+    fn run(&mut self, terminal: &mut ratatui::DefaultTerminal) -> Result<Vec<String>, Box<dyn Error>> {
+        loop {
+            terminal.draw(|frame| self.render(frame))?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(Vec::new()),
+                KeyCode::Enter => return Ok(self.chosen()),
+                KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+                KeyCode::PageDown => self.move_by(PAGE as isize),
+                KeyCode::PageUp => self.move_by(-(PAGE as isize)),
+                KeyCode::Home | KeyCode::Char('g') => self.move_to(0),
+                KeyCode::End | KeyCode::Char('G') => self.move_to(self.fc.features.len().saturating_sub(1)),
+                KeyCode::Char(' ') => {
+                    self.toggle_current();
+                    self.move_by(1);
+                },
+                KeyCode::Char('a') => self.toggle_all(),
+                KeyCode::Char('s') => self.cycle_sort(),
+                KeyCode::Char('r') => self.reverse_sort(),
+                KeyCode::Char('J') => self.detail_scroll = self.detail_scroll.saturating_add(1),
+                KeyCode::Char('K') => self.detail_scroll = self.detail_scroll.saturating_sub(1),
+                _ => (),
+            }
+        }
+    }
+
+    fn current(&self) -> Option<&Feature> {
+        self.state.selected().and_then(|i| self.fc.features.get(i))
+    }
+
+    // Picked ids in listing order, or the highlighted one
+    fn chosen(&self) -> Vec<String> {
+        if self.picked.is_empty() {
+            return self.current().and_then(|f| get_id(&f.id)).into_iter().collect();
+        }
+        self.fc.features.iter().filter_map(|f| get_id(&f.id)).filter(|id| self.picked.contains(id)).collect()
+    }
+
+    fn move_by(&mut self, offset: isize) {
+        let current = self.state.selected().unwrap_or(0) as isize;
+        self.move_to((current + offset).max(0) as usize);
+    }
+
+    fn move_to(&mut self, index: usize) {
+        if self.fc.features.is_empty() {
+            return;
+        }
+        self.state.select(Some(index.min(self.fc.features.len() - 1)));
+        self.detail_scroll = 0;
+    }
+
+    fn toggle_current(&mut self) {
+        if let Some(id) = self.current().and_then(|f| get_id(&f.id)) {
+            if !self.picked.remove(&id) {
+                self.picked.insert(id);
+            }
+        }
+    }
+
+    fn toggle_all(&mut self) {
+        if self.picked.len() == self.fc.features.len() {
+            self.picked.clear();
+        } else {
+            self.picked = self.fc.features.iter().filter_map(|f| get_id(&f.id)).collect();
+        }
+    }
+
+    // Sorts by the next column, keeping the highlighted feature highlighted
+    fn cycle_sort(&mut self) {
+        self.sort = match self.sort {
+            Some((column, _)) if column + 1 < self.columns.len() => Some((column + 1, false)),
+            Some(_) => None,
+            None if self.columns.is_empty() => None,
+            None => Some((0, false)),
+        };
+        self.apply_sort();
+    }
+
+    fn reverse_sort(&mut self) {
+        if let Some((column, descending)) = self.sort {
+            self.sort = Some((column, !descending));
+            self.apply_sort();
+        }
+    }
+
+    fn apply_sort(&mut self) {
+        let highlighted = self.current().map(|f| f.id.clone());
+        if let Some((column, descending)) = self.sort {
+            let key = header(&self.columns[column]).to_lowercase();
+            sort_features(&mut self.fc, &if descending { format!("-{key}") } else { key });
+        }
+        if let Some(index) = highlighted.and_then(|id| self.fc.features.iter().position(|f| f.id == id)) {
+            self.state.select(Some(index));
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let [main, footer] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [list, side] = Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(main);
+        let [details, footprint] = Layout::vertical([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(side);
+
+        self.render_table(frame, list);
+        self.render_details(frame, details);
+        self.render_footprint(frame, footprint);
+        let status = format!(" {} picked  {KEYS}", self.picked.len());
+        frame.render_widget(Paragraph::new(status).style(Style::default().add_modifier(Modifier::DIM)), footer);
+    }
+
+    fn render_table(&mut self, frame: &mut Frame, area: Rect) {
+        let rows: Vec<Vec<String>> = self.fc.features.iter()
+            .map(|f| self.columns.iter().map(|c| column_value(f, c)).collect())
+            .collect();
+        // Ids are long, so they take what's left after the other columns
+        let widths: Vec<Constraint> = std::iter::once(Constraint::Length(1))
+            .chain(self.columns.iter().enumerate().map(|(i, c)| match c {
+                Column::Id => Constraint::Fill(1),
+                _ => {
+                    let widest = rows.iter().map(|r| r[i].chars().count()).max().unwrap_or(0);
+                    Constraint::Length(widest.max(header(c).len() + 1) as u16)
+                },
+            }))
+            .collect();
+        let headers = std::iter::once(String::new()).chain(self.columns.iter().enumerate().map(|(i, c)| {
+            match self.sort {
+                Some((column, descending)) if column == i => format!("{}{}", header(c), if descending { "▼" } else { "▲" }),
+                _ => header(c).to_string(),
+            }
+        }));
+        let table_rows = self.fc.features.iter().zip(rows).map(|(f, values)| {
+            let picked = get_id(&f.id).is_some_and(|id| self.picked.contains(&id));
+            let style = if picked { Style::default().fg(Color::Green) } else { Style::default() };
+            Row::new(std::iter::once(String::from(if picked { "*" } else { " " })).chain(values)).style(style)
+        });
+        let title = format!(" Results ({}) ", self.fc.features.len());
+        let table = Table::new(table_rows, widths)
+            .header(Row::new(headers).style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(Block::bordered().title(title))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, area, &mut self.state);
+    }
+
+    fn render_details(&self, frame: &mut Frame, area: Rect) {
+        let lines = self.current().map(detail_lines).unwrap_or_default();
+        let details = Paragraph::new(lines)
+            .block(Block::bordered().title(" Details "))
+            .wrap(Wrap { trim: false })
+            .scroll((self.detail_scroll, 0));
+        frame.render_widget(details, area);
+    }
+
+    /*
+     * Footprints over a coastline map, zoomed to the highlighted one. Picked
+     * footprints are drawn too, to see how they cover the area together.
+     */
+    fn render_footprint(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Footprint ");
+        let current = if let Some(current) = self.current() { current } else {
+            frame.render_widget(block, area);
+            return;
+        };
+        let [min_x, min_y, max_x, max_y] = footprint_bounds(current).unwrap_or([-180.0, -90.0, 180.0, 90.0]);
+        let picked: Vec<&Feature> = self.fc.features.iter()
+            .filter(|f| get_id(&f.id).is_some_and(|id| self.picked.contains(&id)))
+            .collect();
+        let canvas = Canvas::default()
+            .block(block)
+            .marker(Marker::Braille)
+            .x_bounds([(min_x - MAP_MARGIN).max(-180.0), (max_x + MAP_MARGIN).min(180.0)])
+            .y_bounds([(min_y - MAP_MARGIN).max(-90.0), (max_y + MAP_MARGIN).min(90.0)])
+            .paint(|ctx| {
+                ctx.draw(&Map { resolution: MapResolution::High, color: Color::DarkGray });
+                for feature in &picked {
+                    draw_footprint(ctx, feature, Color::Green);
+                }
+                draw_footprint(ctx, current, Color::Yellow);
+            });
+        frame.render_widget(canvas, area);
+    }
+}
+
+// Everything known about a feature: its properties, then its assets
+fn detail_lines(f: &Feature) -> Vec<Line<'static>> {
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let mut lines = vec![
+        Line::from(Span::styled(get_id(&f.id).unwrap_or_default(), bold)),
+        Line::from(format!("collection: {}", collection_of(f))),
+    ];
+    if let Some(size) = product_size(f) {
+        lines.push(Line::from(format!("size: {}", format_size(size))));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled("Properties", bold)));
+    if let Some(properties) = &f.properties {
+        let mut names: Vec<&String> = properties.keys().collect();
+        names.sort();
+        for name in names {
+            let value = get_value(properties.get(name).cloned()).unwrap_or_default();
+            lines.push(Line::from(vec![Span::styled(format!("{name}: "), Style::default().fg(Color::Cyan)), Span::raw(value)]));
+        }
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled("Assets", bold)));
+    if let Some(assets) = f.foreign_members.as_ref().and_then(|m| m.get("assets")).and_then(|a| a.as_object()) {
+        for (name, asset) in assets {
+            let href = get_value(asset.get("href").cloned()).unwrap_or_default();
+            lines.push(Line::from(vec![Span::styled(format!("{name}: "), Style::default().fg(Color::Cyan)), Span::raw(href)]));
+        }
+    }
+    lines
+}
+
+// Outer rings of a footprint, as [lon, lat] points
+fn footprint_rings(f: &Feature) -> Vec<Vec<Vec<f64>>> {
+    match f.geometry.as_ref().map(|g| &g.value) {
+        Some(GeometryValue::Polygon(rings)) => rings.iter().take(1).cloned().collect(),
+        Some(GeometryValue::MultiPolygon(polygons)) => polygons.iter().filter_map(|rings| rings.first().cloned()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn footprint_bounds(f: &Feature) -> Option<[f64; 4]> {
+    if let Some([min_x, min_y, max_x, max_y]) = f.bbox.as_deref() {
+        return Some([*min_x, *min_y, *max_x, *max_y]);
+    }
+    let points: Vec<Vec<f64>> = footprint_rings(f).into_iter().flatten().collect();
+    let first = points.first()?;
+    Some(points.iter().fold([first[0], first[1], first[0], first[1]], |[min_x, min_y, max_x, max_y], p| {
+        [min_x.min(p[0]), min_y.min(p[1]), max_x.max(p[0]), max_y.max(p[1])]
+    }))
+}
+
+fn draw_footprint(ctx: &mut ratatui::widgets::canvas::Context, f: &Feature, color: Color) {
+    for ring in footprint_rings(f) {
+        for pair in ring.windows(2) {
+            if let [a, b] = pair {
+                ctx.draw(&CanvasLine { x1: a[0], y1: a[1], x2: b[0], y2: b[1], color });
+            }
+        }
+    }
+}
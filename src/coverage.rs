@@ -0,0 +1,208 @@
+use std::error::Error;
+use std::fs::read_to_string;
+
+use geo::{BooleanOps, BoundingRect, ChamberlainDuquetteArea, Coord, MultiPolygon, Polygon, Rect};
+use geojson::{Feature, FeatureCollection, GeoJson, Geometry};
+use log::debug;
+use serde_json::{json, Map, Value};
+
+use crate::args::SearchArgs;
+use crate::tiles::tile;
+use crate::util::{get_id, parse_bbox};
+
+/*
+ * How much of an area of interest (AOI) search results cover, from each
+ * feature's geometry. Areas are on the ellipsoid, so fractions hold up away from
+ * the equator, where lon/lat degrees shrink.
+ */
+
+/*
+ * Coverage of an AOI, by feature and for the whole set. Gaps are the parts of the
+ * AOI no feature covers.
+ */
+#[derive(Debug)]
+pub struct CoverageReport {
+    // Feature id and the fraction of the AOI it covers
+    pub features: Vec<(String, f64)>,
+    pub union: f64,
+    pub aoi_area: f64,
+    pub gaps: MultiPolygon,
+}
+
+/*
+ * The AOI for search args: a GeoJSON file if given, then the bbox, then the
+ * footprints of the requested tiles. None if the search isn't spatial.
+ */
+pub fn search_aoi(args: &SearchArgs) -> Result<Option<MultiPolygon>, Box<dyn Error>> {
+    if let Some(path) = &args.aoi {
+        return read_aoi(path).map(Some);
+    }
+    if let Some(bbox) = &args.bbox {
+        let [min_x, min_y, max_x, max_y] = parse_bbox(bbox).map_err(|e| e.to_string())?;
+        let rect = Rect::new(Coord { x: min_x, y: min_y }, Coord { x: max_x, y: max_y });
+        return Ok(Some(MultiPolygon::new(Vec::from([rect.to_polygon()]))));
+    }
+    if !args.tiles.is_empty() {
+        let mut aoi = MultiPolygon::new(Vec::new());
+        for name in &args.tiles {
+            let footprint = tile(name)?.footprint;
+            let polygon = Polygon::new(footprint.iter().map(|p| Coord { x: p[0], y: p[1] }).collect(), Vec::new());
+            aoi = aoi.union(&polygon);
+        }
+        return Ok(Some(aoi));
+    }
+    Ok(None)
+}
+
+/*
+ * Reads an AOI from a GeoJSON file, which can be a bare geometry, a feature, or a
+ * feature collection, in which case all its polygons are combined.
+ */
+pub fn read_aoi(path: &str) -> Result<MultiPolygon, Box<dyn Error>> {
+    let geojson = read_to_string(path)
+        .map_err(|e| format!("Unable to read AOI {path}: {e}"))?
+        .parse::<GeoJson>()?;
+    let geometries: Vec<Geometry> = match geojson {
+        GeoJson::Geometry(g) => Vec::from([g]),
+        GeoJson::Feature(f) => f.geometry.into_iter().collect(),
+        GeoJson::FeatureCollection(fc) => fc.features.into_iter().filter_map(|f| f.geometry).collect(),
+    };
+    let mut aoi = MultiPolygon::new(Vec::new());
+    for geometry in &geometries {
+        if let Some(polygons) = to_multi_polygon(geometry) {
+            aoi = aoi.union(&polygons);
+        }
+    }
+    if aoi.0.is_empty() {
+        return Err(format!("No polygons in AOI: {path}").into());
+    }
+    Ok(aoi)
+}
+
+// [min lon, min lat, max lon, max lat] of an AOI, for the catalogue query
+pub fn aoi_bbox(aoi: &MultiPolygon) -> Option<[f64; 4]> {
+    aoi.bounding_rect().map(|r| [r.min().x, r.min().y, r.max().x, r.max().y])
+}
+
+fn to_multi_polygon(geometry: &Geometry) -> Option<MultiPolygon> {
+    match geo::Geometry::<f64>::try_from(geometry.value.clone()) {
+        Ok(geo::Geometry::Polygon(p)) => Some(MultiPolygon::new(Vec::from([p]))),
+        Ok(geo::Geometry::MultiPolygon(mp)) => Some(mp),
+        Ok(_) => None,
+        Err(e) => {
+            debug!("Coverage: unusable geometry: {e}");
+            None
+        }
+    }
+}
+
+fn feature_footprint(f: &Feature) -> Option<MultiPolygon> {
+    f.geometry.as_ref().and_then(to_multi_polygon)
+}
+
+/*
+ * Fraction of the AOI a feature covers, 0 to 1. Features without a polygon
+ * geometry cover nothing.
+ */
+pub fn feature_coverage(f: &Feature, aoi: &MultiPolygon) -> f64 {
+    let aoi_area = aoi.chamberlain_duquette_unsigned_area();
+    match feature_footprint(f) {
+        Some(footprint) if aoi_area > 0.0 => footprint.intersection(aoi).chamberlain_duquette_unsigned_area() / aoi_area,
+        _ => 0.0,
+    }
+}
+
+// Drops features covering less than min_coverage percent of the AOI
+pub fn retain_coverage(fc: &mut FeatureCollection, aoi: &MultiPolygon, min_coverage: f64) {
+    fc.features.retain(|f| feature_coverage(f, aoi) * 100.0 >= min_coverage);
+}
+
+pub fn coverage_report(fc: &FeatureCollection, aoi: &MultiPolygon) -> CoverageReport {
+    let mut union = MultiPolygon::new(Vec::new());
+    let mut features: Vec<(String, f64)> = Vec::new();
+    for f in &fc.features {
+        features.push((get_id(&f.id).unwrap_or_default(), feature_coverage(f, aoi)));
+        if let Some(footprint) = feature_footprint(f) {
+            union = union.union(&footprint);
+        }
+    }
+    let aoi_area = aoi.chamberlain_duquette_unsigned_area();
+    let covered = union.intersection(aoi).chamberlain_duquette_unsigned_area();
+    CoverageReport {
+        features,
+        union: if aoi_area > 0.0 { covered / aoi_area } else { 0.0 },
+        aoi_area,
+        gaps: aoi.difference(&union),
+    }
+}
+
+/*
+ * Feature footprints as GeoJSON, keeping the properties useful for picking
+ * scenes, plus coverage when there's an AOI.
+ */
+pub fn footprints_geojson(fc: &FeatureCollection, aoi: &Option<MultiPolygon>) -> FeatureCollection {
+    let features = fc.features.iter().map(|f| {
+        let mut properties = Map::new();
+        properties.insert(String::from("id"), json!(get_id(&f.id)));
+        for name in ["datetime", "cloudCover", "tileId", "relativeOrbitNumber", "productType"] {
+            if let Some(value) = f.properties.as_ref().and_then(|p| p.get(name)) {
+                properties.insert(name.to_string(), value.clone());
+            }
+        }
+        if let Some(aoi) = aoi {
+            properties.insert(String::from("coverage"), json!(feature_coverage(f, aoi)));
+        }
+        Feature {
+            bbox: f.bbox.clone(),
+            geometry: f.geometry.clone(),
+            id: f.id.clone(),
+            properties: Some(properties),
+            foreign_members: None,
+        }
+    }).collect();
+    FeatureCollection { bbox: None, features, foreign_members: None }
+}
+
+/*
+ * Feature footprints as a KML document, one placemark per feature, with the same
+ * properties as the GeoJSON export as extended data.
+ */
+pub fn footprints_kml(fc: &FeatureCollection, aoi: &Option<MultiPolygon>) -> String {
+    let mut placemarks: Vec<String> = Vec::new();
+    for f in &footprints_geojson(fc, aoi).features {
+        let footprint = if let Some(footprint) = feature_footprint(f) { footprint } else { continue };
+        let properties = f.properties.clone().unwrap_or_default();
+        let data: Vec<String> = properties.iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| {
+                let v = if let Value::String(s) = v { s.clone() } else { v.to_string() };
+                format!("<Data name=\"{}\"><value>{}</value></Data>", escape_xml(k), escape_xml(&v))
+            })
+            .collect();
+        let polygons: Vec<String> = footprint.0.iter().map(kml_polygon).collect();
+        placemarks.push(format!(
+            "<Placemark><name>{}</name><ExtendedData>{}</ExtendedData><MultiGeometry>{}</MultiGeometry></Placemark>",
+            escape_xml(&get_id(&f.id).unwrap_or_default()), data.join(""), polygons.join("")
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\"><Document>\n{}\n</Document></kml>\n",
+        placemarks.join("\n")
+    )
+}
+
+fn kml_polygon(polygon: &Polygon) -> String {
+    let ring = |ring: &geo::LineString| ring.coords().map(|c| format!("{},{}", c.x, c.y)).collect::<Vec<String>>().join(" ");
+    let inner: Vec<String> = polygon.interiors().iter()
+        .map(|r| format!("<innerBoundaryIs><LinearRing><coordinates>{}</coordinates></LinearRing></innerBoundaryIs>", ring(r)))
+        .collect();
+    format!(
+        "<Polygon><outerBoundaryIs><LinearRing><coordinates>{}</coordinates></LinearRing></outerBoundaryIs>{}</Polygon>",
+        ring(polygon.exterior()), inner.join("")
+    )
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
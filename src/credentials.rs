@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::env::var;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use log::{debug, warn};
+use secret_service::{EncryptionType, SecretService};
+use serde::{Serialize, Deserialize};
+
+use crate::progress;
+use crate::util::{config_dir, write_private};
+
+pub const ENV_VAR_USER: &str = "COPERNICUS_USER";
+const ENV_VAR_PASS: &str = "COPERNICUS_PASS";
+// Attribute value used to find our items in the OS keyring
+pub const KEYRING_SERVICE: &str = "copernicus-cli";
+// Fallback store for passwords when no keyring is available, kept in the config dir
+const CREDENTIALS_FILE: &str = "credentials.json";
+
+// Related to both CLI ENV and Auth interactions
+#[derive(Default)]
+pub struct Credentials {
+    pub user: Option<String>,
+    pub pass: Option<String>,
+    pub grant: Grant,
+    // Service account id for the client credentials grant
+    pub client_id: Option<String>,
+    // One-time code for accounts with 2FA enabled
+    pub totp: Option<String>,
+}
+
+/*
+ * OAuth grant used to log in. Password is for user accounts. Client credentials
+ * is for service accounts, where the profile's password source holds the secret.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Grant {
+    #[default]
+    Password,
+    ClientCredentials,
+}
+
+/*
+ * Where a profile's password comes from. The env source is what the tool has
+ * always done, i.e. COPERNICUS_PASS from the environment or a .env file.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialSource {
+    #[default]
+    Env,
+    Keyring,
+    File,
+    Command,
+}
+
+/*
+ * Named account, as configured in the config file:
+ *
+ *   [profiles.work]
+ *   user = "someone@example.com"
+ *   source = "command"
+ *   password_command = "pass show copernicus/work"
+ *   totp = true
+ *
+ * Service accounts set grant = "client_credentials" and a client_id instead of a
+ * user, and their password source provides the client secret.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Profile {
+    pub user: Option<String>,
+    #[serde(default)]
+    pub source: CredentialSource,
+    pub password_command: Option<String>,
+    #[serde(default)]
+    pub grant: Grant,
+    pub client_id: Option<String>,
+    // Ask for a one-time code when logging in, or run totp_command to get one
+    #[serde(default)]
+    pub totp: bool,
+    pub totp_command: Option<String>,
+}
+
+pub fn get_env_creds() -> Credentials {
+    Credentials {
+        user: var(ENV_VAR_USER).ok(),
+        pass: var(ENV_VAR_PASS).ok(),
+        ..Default::default()
+    }
+}
+
+/*
+ * Looks up a profile by name, with an error that points at the config file
+ * since that's the only place profiles come from.
+ */
+pub fn find_profile<'a>(name: &str, profiles: &'a HashMap<String, Profile>) -> Result<&'a Profile, Box<dyn Error>> {
+    match profiles.get(name) {
+        Some(profile) => Ok(profile),
+        None => {
+            let config_path = confy::get_configuration_file_path(crate::APP_NAME, None)?;
+            Err(format!("No profile named '{name}'. Profiles are defined in {}", config_path.display()).into())
+        }
+    }
+}
+
+/*
+ * Resolves credentials for a named profile. The user name can still come from
+ * the environment if the profile doesn't set one.
+ */
+pub async fn get_profile_creds(name: &str, profile: &Profile) -> Result<Credentials, Box<dyn Error>> {
+    let user = profile.user.clone().or_else(|| var(ENV_VAR_USER).ok());
+    let pass = match profile.source {
+        CredentialSource::Env => var(ENV_VAR_PASS).ok(),
+        CredentialSource::Keyring => keyring_or_file_lookup(name).await?,
+        CredentialSource::File => file_lookup(name)?,
+        CredentialSource::Command => {
+            if let Some(command) = &profile.password_command {
+                Some(run_password_command(command)?)
+            } else {
+                return Err(format!("Profile '{name}' uses a password command, but password_command isn't set.").into());
+            }
+        },
+    };
+    if pass.is_none() {
+        warn!("No password found for profile '{name}' ({:?}).", profile.source);
+    }
+    // Codes are only good for a short while, so this is left until we know we're
+    // about to log in, which is the only time credentials get resolved.
+    let totp = match (&profile.totp_command, profile.totp) {
+        (Some(command), _) => Some(run_password_command(command)?),
+        (None, true) => Some(prompt_totp(name)?),
+        (None, false) => None,
+    };
+    Ok(Credentials { user, pass, grant: profile.grant, client_id: profile.client_id.clone(), totp })
+}
+
+/*
+ * Asks for a code on the terminal rather than stdin, which may be piped input,
+ * e.g. download --ids-from -, like the password prompt.
+ */
+fn prompt_totp(name: &str) -> Result<String, Box<dyn Error>> {
+    let code = rpassword::prompt_password(format!("One-time code for '{name}': "))
+        .map_err(|e| format!("Unable to ask for a one-time code, set totp_command for '{name}' instead: {e}"))?;
+    match code.trim() {
+        "" => Err("No one-time code entered".into()),
+        code => Ok(code.to_string())
+    }
+}
+
+/*
+ * Saves a password for a profile, to the keyring if it uses one, or the
+ * credentials file. Keyring profiles only fall back to the file, which is
+ * plaintext, when allow_file says so, since otherwise people expecting the
+ * keyring wouldn't know.
+ */
+pub async fn store_password(name: &str, profile: &Profile, pass: &str, allow_file: bool) -> Result<PathBuf, Box<dyn Error>> {
+    match profile.source {
+        CredentialSource::Keyring => match keyring_store(name, pass).await {
+            Ok(_) => Ok(PathBuf::from("keyring")),
+            Err(e) if allow_file => {
+                progress::message(&format!("Keyring unavailable, saving to the credentials file instead: {e}"));
+                file_store(name, Some(pass))
+            },
+            Err(e) => Err(format!(
+                "Keyring unavailable: {e}. Use --allow-file to save the password to {} instead, in plaintext readable only by you.",
+                credentials_path()?.display()
+            ).into()),
+        },
+        CredentialSource::File => file_store(name, Some(pass)),
+        source => Err(format!("Profile '{name}' reads its password from {source:?}, nothing to store.").into())
+    }
+}
+
+/*
+ * Removes any password we stored for a profile, from both the keyring and file.
+ */
+pub async fn remove_password(name: &str) -> Result<(), Box<dyn Error>> {
+    if let Err(e) = keyring_remove(name).await {
+        debug!("Credentials: keyring removal skipped: {e}");
+    }
+    file_store(name, None)?;
+    Ok(())
+}
+
+/*
+ * Runs the user's password (or one-time code) command through the shell and uses
+ * the first line of its output, which matches how tools like `pass` print secrets.
+ */
+fn run_password_command(command: &str) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("sh").arg("-c").arg(command).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("`{command}` exited with {}: {}", output.status, stderr.trim()).into());
+    }
+    let stdout = String::from_utf8(output.stdout)?;
+    match stdout.lines().next() {
+        Some(line) if !line.is_empty() => Ok(line.to_string()),
+        _ => Err(format!("`{command}` printed nothing").into())
+    }
+}
+
+// Keyring
+
+fn password_attributes(name: &str) -> HashMap<&str, &str> {
+    HashMap::from([("service", KEYRING_SERVICE), ("profile", name)])
+}
+
+async fn keyring_or_file_lookup(name: &str) -> Result<Option<String>, Box<dyn Error>> {
+    match keyring_lookup(password_attributes(name)).await {
+        Ok(Some(secret)) => Ok(Some(String::from_utf8(secret)?)),
+        Ok(None) => Ok(None),
+        Err(e) => {
+            debug!("Credentials: keyring unavailable ({e}), trying credentials file.");
+            file_lookup(name)
+        }
+    }
+}
+
+async fn keyring_store(name: &str, pass: &str) -> Result<(), Box<dyn Error>> {
+    let label = format!("Copernicus CLI ({name})");
+    keyring_set(&label, password_attributes(name), pass.as_bytes()).await
+}
+
+async fn keyring_remove(name: &str) -> Result<(), Box<dyn Error>> {
+    keyring_delete(password_attributes(name)).await
+}
+
+/*
+ * Generic keyring access, shared with anything else we keep in the keyring.
+ * Items are matched on attributes rather than labels.
+ */
+pub async fn keyring_lookup(attributes: HashMap<&str, &str>) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let ss = SecretService::connect(EncryptionType::Dh).await?;
+    let collection = ss.get_default_collection().await?;
+    collection.ensure_unlocked().await?;
+    let items = collection.search_items(attributes).await?;
+    match items.first() {
+        Some(item) => Ok(Some(item.get_secret().await?)),
+        None => Ok(None)
+    }
+}
+
+pub async fn keyring_set(label: &str, attributes: HashMap<&str, &str>, secret: &[u8]) -> Result<(), Box<dyn Error>> {
+    let ss = SecretService::connect(EncryptionType::Dh).await?;
+    let collection = ss.get_default_collection().await?;
+    collection.ensure_unlocked().await?;
+    collection.create_item(label, attributes, secret, true, "text/plain").await?;
+    Ok(())
+}
+
+pub async fn keyring_delete(attributes: HashMap<&str, &str>) -> Result<(), Box<dyn Error>> {
+    let ss = SecretService::connect(EncryptionType::Dh).await?;
+    let collection = ss.get_default_collection().await?;
+    collection.ensure_unlocked().await?;
+    for item in collection.search_items(attributes).await? {
+        item.delete().await?;
+    }
+    Ok(())
+}
+
+// Credentials file
+
+fn credentials_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(config_dir()?.join(CREDENTIALS_FILE))
+}
+
+fn read_credentials_file() -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let path = credentials_path()?;
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn file_lookup(name: &str) -> Result<Option<String>, Box<dyn Error>> {
+    Ok(read_credentials_file()?.remove(name))
+}
+
+/*
+ * Sets or, given None, clears the password for a profile in the credentials file.
+ */
+fn file_store(name: &str, pass: Option<&str>) -> Result<PathBuf, Box<dyn Error>> {
+    let mut passwords = read_credentials_file()?;
+    let path = credentials_path()?;
+    match pass {
+        Some(pass) => { passwords.insert(name.to_string(), pass.to_string()); },
+        None => if passwords.remove(name).is_none() {
+            return Ok(path);
+        },
+    }
+    write_private(&path, serde_json::to_string_pretty(&passwords)?.as_bytes())?;
+    Ok(path)
+}
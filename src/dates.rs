@@ -0,0 +1,350 @@
+use std::error::Error;
+
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, TimeDelta, Utc};
+
+use crate::args::{SearchArgs, TimeAdjust};
+use crate::util::parse_date;
+
+/*
+ * Date expressions beyond plain dates: relative ones like 7d or last-month, ISO
+ * 8601 durations and intervals, and seasonal windows repeated over years.
+ */
+
+/*
+ * Relative dates, from now: today, yesterday, now, an amount of time ago (7d,
+ * -2w, 3m, 1y, 12h, or an ISO duration like P30D), or a whole period
+ * (last-week, last-month, last-year, this-week, this-month, this-year). Periods
+ * give their start for --from and their end for --to. Days and longer round to
+ * the start or end of the day the same way.
+ */
+pub fn parse_relative(s: &str, should_adjust: Option<TimeAdjust>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let ceil = matches!(should_adjust, Some(TimeAdjust::Ceil));
+    let day_bound = |date: NaiveDate| if ceil { end_of_day(date) } else { start_of_day(date) };
+    let today = now.date_naive();
+    let expression = s.trim().to_lowercase();
+    let last = expression.starts_with("last");
+    match expression.as_str() {
+        "now" => Some(now),
+        "today" => Some(day_bound(today)),
+        "yesterday" => Some(day_bound(today - Days::new(1))),
+        "this-week" | "last-week" => {
+            let monday = today - Days::new(today.weekday().num_days_from_monday() as u64);
+            let start = if last { monday - Days::new(7) } else { monday };
+            Some(if ceil { end_of_day(start + Days::new(6)) } else { start_of_day(start) })
+        },
+        "this-month" | "last-month" => {
+            let first = today.with_day(1)?;
+            let start = if last { first.checked_sub_months(Months::new(1))? } else { first };
+            Some(if ceil { end_of_day(start.checked_add_months(Months::new(1))? - Days::new(1)) } else { start_of_day(start) })
+        },
+        "this-year" | "last-year" => {
+            let year = if last { today.year() - 1 } else { today.year() };
+            Some(if ceil { end_of_day(NaiveDate::from_ymd_opt(year, 12, 31)?) } else { start_of_day(NaiveDate::from_ymd_opt(year, 1, 1)?) })
+        },
+        other => {
+            let ago = other.trim_start_matches('-');
+            let duration = parse_duration(ago).or_else(|| parse_short_duration(ago))?;
+            let dt = duration.before(now)?;
+            // Whole days or more ago round to the day, like plain dates do
+            Some(if duration.is_whole_days() { day_bound(dt.date_naive()) } else { dt })
+        },
+    }
+}
+
+/*
+ * An amount of calendar time. Months and years vary in length, so they're kept
+ * apart from days and seconds and applied with calendar arithmetic.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CalendarDuration {
+    pub months: u32,
+    pub days: u64,
+    pub seconds: i64,
+}
+
+impl CalendarDuration {
+    pub fn before(&self, dt: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let dt = dt.checked_sub_months(Months::new(self.months))?.checked_sub_days(Days::new(self.days))?;
+        dt.checked_sub_signed(TimeDelta::try_seconds(self.seconds)?)
+    }
+
+    pub fn after(&self, dt: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let dt = dt.checked_add_months(Months::new(self.months))?.checked_add_days(Days::new(self.days))?;
+        dt.checked_add_signed(TimeDelta::try_seconds(self.seconds)?)
+    }
+
+    fn is_whole_days(&self) -> bool {
+        self.seconds == 0
+    }
+}
+
+/*
+ * ISO 8601 durations, e.g. P30D, P1Y2M, P2W or PT12H.
+ */
+pub fn parse_duration(s: &str) -> Option<CalendarDuration> {
+    let rest = s.trim().to_uppercase();
+    let rest = rest.strip_prefix('P')?;
+    let (date_part, time_part) = match rest.split_once('T') {
+        Some((date, time)) => (date.to_string(), time.to_string()),
+        None => (rest.to_string(), String::new()),
+    };
+    if date_part.is_empty() && time_part.is_empty() {
+        return None;
+    }
+    // Amounts too big to add up are refused, like any other bad duration
+    let mut duration = CalendarDuration::default();
+    for (amount, unit) in duration_parts(&date_part)? {
+        match unit {
+            'Y' => duration.months = duration.months.checked_add(months(amount, 12)?)?,
+            'M' => duration.months = duration.months.checked_add(months(amount, 1)?)?,
+            'W' => duration.days = duration.days.checked_add(amount.checked_mul(7)?)?,
+            'D' => duration.days = duration.days.checked_add(amount)?,
+            _ => return None,
+        }
+    }
+    for (amount, unit) in duration_parts(&time_part)? {
+        match unit {
+            'H' => duration.seconds = duration.seconds.checked_add(seconds(amount, 3600)?)?,
+            'M' => duration.seconds = duration.seconds.checked_add(seconds(amount, 60)?)?,
+            'S' => duration.seconds = duration.seconds.checked_add(seconds(amount, 1)?)?,
+            _ => return None,
+        }
+    }
+    Some(duration)
+}
+
+// An amount of a unit in months, or seconds, if it fits
+fn months(amount: u64, per_unit: u32) -> Option<u32> {
+    u32::try_from(amount).ok()?.checked_mul(per_unit)
+}
+
+fn seconds(amount: u64, per_unit: i64) -> Option<i64> {
+    i64::try_from(amount).ok()?.checked_mul(per_unit)
+}
+
+// Splits e.g. 1Y2M into [(1, 'Y'), (2, 'M')]
+fn duration_parts(s: &str) -> Option<Vec<(u64, char)>> {
+    let mut parts = Vec::new();
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+        } else {
+            parts.push((number.parse().ok()?, c));
+            number.clear();
+        }
+    }
+    if number.is_empty() { Some(parts) } else { None }
+}
+
+// Shorthands like 7d, 2w, 3m, 1y and 12h
+fn parse_short_duration(s: &str) -> Option<CalendarDuration> {
+    let unit = s.chars().last()?;
+    let amount: u64 = s[..s.len() - unit.len_utf8()].parse().ok()?;
+    match unit {
+        'h' => Some(CalendarDuration { seconds: seconds(amount, 3600)?, ..Default::default() }),
+        'd' => Some(CalendarDuration { days: amount, ..Default::default() }),
+        'w' => Some(CalendarDuration { days: amount.checked_mul(7)?, ..Default::default() }),
+        'm' => Some(CalendarDuration { months: months(amount, 1)?, ..Default::default() }),
+        'y' => Some(CalendarDuration { months: months(amount, 12)?, ..Default::default() }),
+        _ => None,
+    }
+}
+
+/*
+ * A span of time for --interval, as an ISO 8601 interval: start/end,
+ * start/duration, duration/end, or a duration alone, which ends now. Either end
+ * can be any date --from and --to take.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct DateRange {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+pub fn parse_interval(s: &str) -> Result<DateRange, Box<dyn Error + Send + Sync>> {
+    let now = Utc::now();
+    let range = match s.split_once('/') {
+        Some((start, end)) => match (parse_duration(start), parse_duration(end)) {
+            (None, Some(duration)) => {
+                let from = parse_date(start, Some(TimeAdjust::Floor))?;
+                DateRange { from, to: duration.after(from).ok_or(format!("Interval out of range: {s}"))? }
+            },
+            (Some(duration), None) => {
+                // Counted back from the start of the end date, which is included
+                let from = duration.before(parse_date(end, Some(TimeAdjust::Floor))?).ok_or(format!("Interval out of range: {s}"))?;
+                DateRange { from, to: parse_date(end, Some(TimeAdjust::Ceil))? }
+            },
+            (None, None) => DateRange {
+                from: parse_date(start, Some(TimeAdjust::Floor))?,
+                to: parse_date(end, Some(TimeAdjust::Ceil))?,
+            },
+            (Some(_), Some(_)) => return Err(format!("An interval needs a start or end, not just durations: {s}").into()),
+        },
+        None => {
+            let duration = parse_duration(s).ok_or(format!("Unable to parse interval: {s}, e.g. 2024-06-01/P30D"))?;
+            DateRange { from: duration.before(now).ok_or(format!("Interval out of range: {s}"))?, to: now }
+        },
+    };
+    if range.from > range.to {
+        return Err(format!("Interval ends before it starts: {s}").into());
+    }
+    Ok(range)
+}
+
+/*
+ * An inclusive span of months or years, e.g. 6-8 or 2019-2024, or a single one.
+ * Month spans can wrap around the new year, e.g. 12-2.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Span {
+    pub start: i32,
+    pub end: i32,
+}
+
+pub fn parse_month_span(s: &str) -> Result<Span, Box<dyn Error + Send + Sync>> {
+    let span = parse_span(s)?;
+    if !(1..=12).contains(&span.start) || !(1..=12).contains(&span.end) {
+        return Err(format!("Months go from 1 to 12: {s}").into());
+    }
+    Ok(span)
+}
+
+pub fn parse_year_span(s: &str) -> Result<Span, Box<dyn Error + Send + Sync>> {
+    let span = parse_span(s)?;
+    if span.start > span.end {
+        return Err(format!("Year spans go from earliest to latest: {s}").into());
+    }
+    Ok(span)
+}
+
+fn parse_span(s: &str) -> Result<Span, Box<dyn Error + Send + Sync>> {
+    let parse = |v: &str| v.trim().parse::<i32>().map_err(|_| format!("Unable to parse span: {s}, e.g. 6-8"));
+    match s.split_once('-') {
+        Some((start, end)) => Ok(Span { start: parse(start)?, end: parse(end)? }),
+        None => {
+            let single = parse(s)?;
+            Ok(Span { start: single, end: single })
+        }
+    }
+}
+
+/*
+ * Expands a month span into a date range for each year, e.g. June to August of
+ * 2019 through 2024. Spans wrapping the new year run into the next year, so
+ * 12-2 for 2020 is December 2020 to February 2021. Ranges are clipped to
+ * bounds, where given.
+ */
+pub fn seasonal_ranges(
+    months: Span, years: Span, bounds: (Option<DateTime<Utc>>, Option<DateTime<Utc>>)
+) -> Vec<DateRange> {
+    let mut ranges = Vec::new();
+    for year in years.start..=years.end {
+        let end_year = if months.end < months.start { year + 1 } else { year };
+        let start = NaiveDate::from_ymd_opt(year, months.start as u32, 1);
+        let end = NaiveDate::from_ymd_opt(end_year, months.end as u32, 1)
+            .and_then(|d| d.checked_add_months(Months::new(1)))
+            .map(|d| d - Days::new(1));
+        let (start, end) = if let (Some(start), Some(end)) = (start, end) { (start, end) } else { continue };
+        let from = bounds.0.map_or(start_of_day(start), |b| b.max(start_of_day(start)));
+        let to = bounds.1.map_or(end_of_day(end), |b| b.min(end_of_day(end)));
+        if from <= to {
+            ranges.push(DateRange { from, to });
+        }
+    }
+    ranges
+}
+
+/*
+ * The date ranges to search for search args with --months, or None for a
+ * single search. Years default to those --from/--to or --interval span.
+ */
+pub fn seasonal_search_ranges(args: &SearchArgs) -> Result<Option<Vec<DateRange>>, Box<dyn Error>> {
+    let months = match (args.months, args.years) {
+        (Some(months), _) => months,
+        (None, Some(_)) => return Err("--years needs --months too, e.g. --months 6-8".into()),
+        (None, None) => return Ok(None),
+    };
+    let bounds = match args.interval {
+        Some(interval) => (Some(interval.from), Some(interval.to)),
+        None => (args.from, args.to),
+    };
+    let years = match (args.years, bounds) {
+        (Some(years), _) => years,
+        (None, (Some(from), Some(to))) => Span { start: from.year(), end: to.year() },
+        _ => return Err("--months needs --years, or both --from and --to, to know which years to search".into()),
+    };
+    Ok(Some(seasonal_ranges(months, years, bounds)))
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+fn end_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(23, 59, 59).unwrap().and_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    const NOW: &str = "2024-03-15T10:30:00Z";
+
+    #[test]
+    fn gives_the_start_and_end_of_periods() {
+        assert_eq!(parse_relative("last-month", Some(TimeAdjust::Floor), at(NOW)), Some(at("2024-02-01T00:00:00Z")));
+        assert_eq!(parse_relative("last-month", Some(TimeAdjust::Ceil), at(NOW)), Some(at("2024-02-29T23:59:59Z")));
+    }
+
+    #[test]
+    fn rounds_whole_days_only() {
+        assert_eq!(parse_relative("7d", Some(TimeAdjust::Floor), at(NOW)), Some(at("2024-03-08T00:00:00Z")));
+        assert_eq!(parse_relative("7d", Some(TimeAdjust::Ceil), at(NOW)), Some(at("2024-03-08T23:59:59Z")));
+        assert_eq!(parse_relative("12h", Some(TimeAdjust::Floor), at(NOW)), Some(at("2024-03-14T22:30:00Z")));
+    }
+
+    #[test]
+    fn parses_iso_durations() {
+        assert_eq!(parse_duration("P1Y2M"), Some(CalendarDuration { months: 14, ..Default::default() }));
+        assert_eq!(parse_duration("P2WT12H"), Some(CalendarDuration { days: 14, seconds: 43200, ..Default::default() }));
+        assert_eq!(parse_duration("P"), None);
+        assert_eq!(parse_duration("P1X"), None);
+    }
+
+    #[test]
+    fn refuses_durations_too_large_to_add_up() {
+        assert_eq!(parse_duration("P99999999999Y"), None);
+        assert_eq!(parse_relative("99999999999y", None, at(NOW)), None);
+    }
+
+    #[test]
+    fn parses_intervals() {
+        let range = parse_interval("2024-06-01/P30D").unwrap();
+        assert_eq!((range.from, range.to), (at("2024-06-01T00:00:00Z"), at("2024-07-01T00:00:00Z")));
+        // The end date is included, and the duration counted back from its start
+        let range = parse_interval("P30D/2024-06-30").unwrap();
+        assert_eq!((range.from, range.to), (at("2024-05-31T00:00:00Z"), at("2024-06-30T23:59:59Z")));
+        let error = parse_interval("2024-06-10/2024-06-01").unwrap_err();
+        assert_eq!(error.to_string(), "Interval ends before it starts: 2024-06-10/2024-06-01");
+    }
+
+    #[test]
+    fn wraps_seasons_over_the_new_year_within_bounds() {
+        let ranges = seasonal_ranges(
+            Span { start: 12, end: 2 }, Span { start: 2020, end: 2021 }, (Some(at("2021-01-15T00:00:00Z")), None)
+        );
+        let ranges: Vec<_> = ranges.iter().map(|r| (r.from, r.to)).collect();
+        assert_eq!(ranges, [
+            (at("2021-01-15T00:00:00Z"), at("2021-02-28T23:59:59Z")),
+            (at("2021-12-01T00:00:00Z"), at("2022-02-28T23:59:59Z")),
+        ]);
+        // Seasons entirely outside the bounds are left out
+        let ranges = seasonal_ranges(Span { start: 6, end: 8 }, Span { start: 2020, end: 2021 }, (None, Some(at("2020-12-31T00:00:00Z"))));
+        assert_eq!(ranges.len(), 1);
+    }
+}
@@ -1,3 +1,4 @@
+extern crate aes_gcm;
 extern crate chrono;
 extern crate clap;
 extern crate colored;
@@ -5,65 +6,133 @@ extern crate confy;
 extern crate dotenv;
 extern crate env_logger;
 extern crate futures_util;
+extern crate geo;
 extern crate geojson;
 extern crate log;
+extern crate quick_xml;
+extern crate ratatui;
 extern crate reqwest;
+#[cfg(unix)]
+extern crate rustix;
+extern crate rpassword;
+extern crate secret_service;
 extern crate serde;
 extern crate serde_json;
 extern crate spinners;
+extern crate terminal_size;
 extern crate tokio;
 extern crate url;
+extern crate zip;
 
+mod archive;
 mod args;
 mod api;
+mod browse;
+mod coverage;
+mod credentials;
+mod dates;
+mod naming;
+mod odata;
+mod plan;
+mod product_list;
+mod progress;
+mod queue;
+mod results;
+mod runs;
+mod select;
+mod sentinel1;
+mod table;
+mod theme;
+mod throttle;
+mod tiles;
+mod tokens;
 mod util;
 
-use std::env::var;
+use std::collections::HashMap;
 use std::error::Error;
+use std::fs::write;
+use std::path::Path;
 
+use chrono::Utc;
 use clap::Parser;
 use dotenv::dotenv;
+use geo::MultiPolygon;
+use geojson::{Feature, FeatureCollection};
 use log::info;
 use serde::{Serialize, Deserialize};
-use spinners::{Spinner, Spinners};
 
-use args::{CliArgs, Mode};
-use api::{AuthDetails, check_auth, download_imagery, search_imagery};
-use util::format_feature_collection;
+use archive::{extract_archive, inspect_archive, safe_dir_for};
+use args::{AuthAction, CliArgs, CredentialsAction, FootprintFormat, Mode, PublishedSince, QueueAction, ResultFormat, SearchArgs};
+use api::{
+    AuthDetails, DEFAULT_TRUSTED_HOSTS, QueryParams, STREAM_DESTINATION, authenticate_credentials, check_auth,
+    download_client, download_imagery, download_to_writer, list_collections, needs_credentials, revoke_authentication,
+    search_imagery,
+};
+use browse::browse;
+use coverage::{aoi_bbox, coverage_report, footprints_geojson, footprints_kml, retain_coverage, search_aoi};
+use credentials::{
+    Credentials, ENV_VAR_USER, Grant, Profile, find_profile, get_env_creds, get_profile_creds,
+    remove_password, store_password,
+};
+use dates::seasonal_search_ranges;
+use plan::{PlanItem, plan_downloads};
+use product_list::{ProductList, read_product_list};
+use progress::set_progress_style;
+use queue::{ItemState, Queue};
+use results::{load_last_results, pick_results, save_last_results};
+use runs::{RunLog, latest_publication};
+use select::select_best;
+use table::sort_features;
+use theme::apply_color_choice;
+use tokens::{DEFAULT_PROFILE, TokenStore, format_token_status};
+use tiles::{tiles_bbox, tiles_covering};
+use util::{DisplayConfig, format_collections, format_coverage, format_plan, format_product_info, format_queue, format_results, format_tiles};
 
 const APP_NAME: &str = "COPERNICUS-CLI";
-const ENV_VAR_USER: &str = "COPERNICUS_USER";
-const ENV_VAR_PASS: &str = "COPERNICUS_PASS";
+// Product ids looked up per catalogue request
+const LOOKUP_SIZE: usize = 50;
 
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Config {
     version: u8,
+    // Tokens used to be kept here in plaintext. Only read now, to move them to
+    // the token store, and never written back.
+    #[serde(default, skip_serializing)]
     auth_details: Option<AuthDetails>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+    // Encrypt stored tokens with a key kept in the OS keyring
+    #[serde(default)]
+    encrypt_tokens: bool,
+    #[serde(default)]
+    display: DisplayConfig,
+    // Default for --name-template
+    #[serde(default)]
+    name_template: Option<String>,
+    // Hosts downloads can send tokens to when redirected, see get_following_redirects
+    #[serde(default = "default_trusted_hosts")]
+    trusted_hosts: Vec<String>,
+}
+
+fn default_trusted_hosts() -> Vec<String> {
+    DEFAULT_TRUSTED_HOSTS.iter().map(|h| h.to_string()).collect()
 }
 
 impl ::std::default::Default for Config {
     fn default() -> Self {
         Self {
             version: 1,
-            auth_details: Option::None
+            auth_details: Option::None,
+            profiles: HashMap::new(),
+            encrypt_tokens: false,
+            display: DisplayConfig::default(),
+            name_template: None,
+            trusted_hosts: default_trusted_hosts(),
         }
     }
 }
 
-fn get_env_creds() -> Credentials {
-    Credentials {
-        user: var(ENV_VAR_USER).ok(),
-        pass: var(ENV_VAR_PASS).ok()
-    }
-}
-
-// Related to both CLI ENV and Auth interactions
-struct Credentials {
-    pub user: Option<String>,
-    pub pass: Option<String>
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
@@ -72,50 +141,538 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Use Result type instead, use ? here to exit immediately
     // let args = get_args()?;
     let args = CliArgs::parse();
+    apply_color_choice(&args.color);
+    set_progress_style(&args.progress, args.quiet);
 
     let mut config: Config = confy::load(APP_NAME, None)?;
-    let credentials = get_env_creds();
+    let token_store = TokenStore { encrypt: config.encrypt_tokens };
+
+    // Move tokens out of configs written by older versions
+    if let Some(old_auth_details) = config.auth_details.take() {
+        info!("Moving stored tokens out of the config file...");
+        token_store.save(DEFAULT_PROFILE, &old_auth_details).await?;
+        confy::store(APP_NAME, None, &config)?;
+    }
+
+    let client = reqwest::Client::new();
+
+    // Catalogue queries are public, so only commands that fetch products or manage
+    // sessions ask for auth, and only when they get to that point.
+    match args.mode {
+        Mode::Search(search_command_args) => {
+            let search_args = search_command_args.search;
+            let (mut fc, aoi) = search(&client, &search_args).await?;
+            if let Some(key) = &search_args.sort_by {
+                sort_features(&mut fc, key);
+            }
+            save_last_results(&fc)?;
+            match search_command_args.format {
+                Some(ResultFormat::Geojson) => println!("{fc}"),
+                Some(ResultFormat::Ndjson) => fc.features.iter().for_each(|f| println!("{f}")),
+                None => {
+                    println!("Search results:\n{}", format_results(&fc, &search_args, &config.display));
+                    print_coverage(&fc, &aoi, &search_args, &config.display);
+                },
+            }
+            Ok(())
+        },
+        Mode::Select(select_args) => {
+            let (fc, aoi) = search(&client, &select_args.search).await?;
+            let mut selected = select_best(&fc, &select_args.per);
+            if let Some(key) = &select_args.search.sort_by {
+                sort_features(&mut selected, key);
+            }
+            save_last_results(&selected)?;
+            println!(
+                "Selected {} of {} results:\n{}",
+                selected.features.len(), fc.features.len(), format_results(&selected, &select_args.search, &config.display)
+            );
+            print_coverage(&selected, &aoi, &select_args.search, &config.display);
+            Ok(())
+        },
+        Mode::Browse(browse_args) => {
+            let (mut fc, _) = search(&client, &browse_args.search).await?;
+            if fc.features.is_empty() {
+                println!("No results to browse.");
+                return Ok(());
+            }
+            if let Some(key) = &browse_args.search.sort_by {
+                sort_features(&mut fc, key);
+            }
+            let ids = browse(fc, &browse_args.search.columns)?;
+            if ids.is_empty() {
+                return Ok(());
+            }
+            let mut queue = Queue::load()?;
+            let name_template = browse_args.name_template.clone().or(config.name_template.clone());
+            let added = queue.add(&ids, &browse_args.output_dir, &name_template)?;
+            queue.save()?;
+            println!("Queued {added} of {} product(s). {} pending, run them with: queue run", ids.len(), queue.count(ItemState::Pending));
+            Ok(())
+        },
+        Mode::Collections => {
+            let task = progress::task("Fetching collections...");
+            let collections = list_collections(&client).await?;
+            task.finish();
+            println!("Collections:\n{}", format_collections(&collections, &config.display));
+            Ok(())
+        },
+        Mode::Tiles(tiles_args) => {
+            println!("Tiles:\n{}", format_tiles(&tiles_covering(&tiles_args.bbox)?, &config.display));
+            Ok(())
+        },
+        Mode::ExportFootprints(export_args) => {
+            let (fc, aoi) = search(&client, &export_args.search).await?;
+            let contents = match export_args.format {
+                FootprintFormat::Geojson => footprints_geojson(&fc, &aoi).to_string(),
+                FootprintFormat::Kml => footprints_kml(&fc, &aoi),
+            };
+            match export_args.output {
+                Some(path) => {
+                    write(&path, contents)?;
+                    println!("{} footprints written to: {path}", fc.features.len());
+                },
+                None => println!("{contents}"),
+            }
+            Ok(())
+        },
+        Mode::Download(download_args) => {
+            // Results of the last search are kept whole, so only ids need looking up
+            let features = if download_args.last || !download_args.results.is_empty() {
+                let last = load_last_results()?;
+                if last.features.is_empty() {
+                    return Err("The last search had no results.".into());
+                }
+                if download_args.all {
+                    last.features
+                } else if download_args.results.is_empty() {
+                    return Err("Pick results of the last search by number, e.g. download 1 4 7, or use --all".into());
+                } else {
+                    pick_results(&last, &download_args.results)?
+                }
+            } else {
+                let mut list = ProductList::default();
+                for id in download_args.ids.iter().flat_map(|ids| ids.split(',')).map(str::trim).filter(|id| !id.is_empty()) {
+                    list.add_id(id);
+                }
+                if let Some(source) = &download_args.ids_from {
+                    read_product_list(source, &mut list)?;
+                }
+                if list.is_empty() {
+                    return Err("Nothing to download. Give --ids, --ids-from, or results of the last search by number".into());
+                }
+                let mut features = list.features;
+                if !list.ids.is_empty() {
+                    let task = progress::task("Querying for imagery with id...");
+                    let found = lookup_products(&client, &list.ids).await?;
+                    task.finish();
+                    for id in list.ids.iter().filter(|id| !found.iter().any(|f| util::get_id(&f.id).as_ref() == Some(id))) {
+                        progress::message(&format!("No imagery found for id: {id}"));
+                    }
+                    features.extend(found);
+                }
+                features
+            };
+            if features.is_empty() {
+                return Err("No imagery found to download.".into());
+            }
+
+            // -o - streams the product to stdout, e.g. into unzip -p, so there's only room for one
+            if download_args.output_dir.as_deref() == Some(STREAM_DESTINATION) {
+                if features.len() > 1 {
+                    return Err(format!("Only one product can be written to stdout, not {}", features.len()).into());
+                }
+                if download_args.extract || download_args.dry_run {
+                    return Err("--extract and --dry-run work with files, so can't be used with -o -".into());
+                }
+                if let Some(window) = &download_args.throttle.window {
+                    window.wait_until_open().await;
+                }
+                let auth_details = get_auth(&args.profile, &config, &token_store).await?;
+                let downloads = download_client()?;
+                let mut stdout = tokio::io::stdout();
+                let download = download_to_writer(
+                    &downloads, &auth_details, &features[0], &mut stdout, download_args.throttle.limit_rate, &config.trusted_hosts
+                );
+                let id = util::get_id(&features[0].id).unwrap_or_default();
+                download.await.inspect_err(|e| progress::download_failed(&id, &e.to_string()))?;
+                return Ok(());
+            }
+
+            let name_template = download_args.name_template.clone().or(config.name_template.clone());
+            let task = progress::task("Planning downloads...");
+            // Sizes are only all looked up for a dry run, which is there to show them
+            let plan = plan_downloads(&client, &features, &download_args.output_dir, &name_template, download_args.dry_run).await?;
+            task.finish();
+            if download_args.dry_run {
+                println!("Download plan:\n{}", format_plan(&plan, &config.display));
+                return Ok(());
+            }
+            plan.check_space()?;
+            for item in plan.items.iter().filter(|i| i.present) {
+                println!("Already present, skipping: {}{}", item.destination.display(), format_unverified(item));
+            }
+            if plan.pending().count() == 0 {
+                return Ok(());
+            }
+
+            let downloads = download_client()?;
+            let known_size = plan.unknown_sizes() == 0;
+            progress::batch_started(plan.pending().count(), Some(plan.total_bytes()).filter(|_| known_size));
+            for item in plan.pending() {
+                if let Some(window) = &download_args.throttle.window {
+                    window.wait_until_open().await;
+                }
+                // Checked for each product, since tokens can expire during long batches or waits
+                let auth_details = get_auth(&args.profile, &config, &token_store).await?;
+                let download = download_imagery(
+                    &downloads, &auth_details, &item.feature, download_args.output_dir.clone(), &name_template,
+                    download_args.throttle.limit_rate, &config.trusted_hosts
+                );
+                let details = download.await.inspect_err(|e| progress::download_failed(&item.id, &e.to_string()))?;
+                println!("{} bytes, saved to: {}", details.size, details.destination.display());
+                if download_args.extract {
+                    let destination = safe_dir_for(&details.destination);
+                    let count = extract_archive(&details.destination, &destination)?;
+                    println!("{count} files extracted to: {}", destination.display());
+                }
+            }
+            Ok(())
+        },
+        Mode::Queue(queue_args) => manage_queue(&client, &args.profile, &config, &token_store, queue_args.action).await,
+        Mode::Inspect(inspect_args) => {
+            let info = inspect_archive(Path::new(&inspect_args.archive))?;
+            println!("{}", format_product_info(&info, &config.display));
+            Ok(())
+        },
+        Mode::Credentials(credentials_args) => manage_credentials(&args.profile, &config, credentials_args.action).await,
+        Mode::Auth(auth_args) => manage_auth(&args.profile, &config, &token_store, auth_args.action).await,
+    }
+}
+
+/*
+ * Searches for imagery, then drops results that barely touch the area of
+ * interest if asked to. The AOI, if there is one, comes back too for reporting
+ * coverage. An AOI file also bounds the query, unless there's a bbox. Seasonal
+ * searches (--months) run once per year.
+ */
+async fn search(client: &reqwest::Client, search_args: &SearchArgs) -> Result<(FeatureCollection, Option<MultiPolygon>), Box<dyn Error>> {
+    let aoi = search_aoi(search_args)?;
+    if search_args.min_coverage.is_some() && aoi.is_none() {
+        return Err("--min-coverage needs an area of interest: --aoi, --bbox or --tile".into());
+    }
+    let mut search_args = search_args.clone();
+    check_sentinel1_options(&mut search_args)?;
+    if search_args.bbox.is_none() && search_args.aoi.is_some() {
+        search_args.bbox = aoi.as_ref().and_then(aoi_bbox).map(|b| b.map(|v| v.to_string()).join(","));
+    }
+    // Tiles make for a spatial query too, unless there's a bbox already
+    if search_args.bbox.is_none() && !search_args.tiles.is_empty() {
+        search_args.bbox = Some(tiles_bbox(&search_args.tiles)?.map(|v| v.to_string()).join(","));
+    }
+    let ranges = seasonal_search_ranges(&search_args)?;
+    // Publication times are the catalogue's, so runs are timed from before searching
+    let started = Utc::now();
+    if search_args.published_since == Some(PublishedSince::LastRun) {
+        match RunLog::load()?.last_run(&search_args) {
+            Some(last_run) => {
+                info!("Searching for products published since the last run, {last_run}");
+                search_args.published_since = Some(PublishedSince::Date(last_run));
+            },
+            None => info!("No earlier run of this search, so searching everything published"),
+        }
+    }
+    let task = progress::task("Searching for imagery...");
+    let mut fc = match ranges {
+        None => search_imagery(client, search_args.clone().into()).await?,
+        // A search per season, merged, leaving out anything found twice
+        Some(ranges) => {
+            let mut merged = FeatureCollection { bbox: None, features: Vec::new(), foreign_members: None };
+            for range in ranges {
+                let season_args = SearchArgs { from: Some(range.from), to: Some(range.to), interval: None, ..search_args.clone() };
+                for feature in search_imagery(client, season_args.into()).await?.features {
+                    if !merged.features.iter().any(|f| f.id == feature.id) {
+                        merged.features.push(feature);
+                    }
+                }
+            }
+            merged
+        },
+    };
+    task.finish();
+    if search_args.published_since.is_some() {
+        // Without a limit or page everything published was fetched, so the next run
+        // starts from this one. Otherwise some may have been left out, so it starts
+        // from the newest product fetched, and stays put if there were none.
+        let complete = search_args.limit.is_none() && search_args.page.is_none();
+        if let Some(marker) = if complete { Some(started) } else { latest_publication(&fc) } {
+            let mut run_log = RunLog::load()?;
+            run_log.record(&search_args, marker);
+            run_log.save()?;
+        }
+    }
+    if let (Some(min_coverage), Some(aoi)) = (search_args.min_coverage, &aoi) {
+        retain_coverage(&mut fc, aoi, min_coverage);
+    }
+    Ok((fc, aoi))
+}
+
+/*
+ * Sentinel-1 options only make sense searching Sentinel-1, so they pick it when
+ * no collection is given, and are refused for any other.
+ */
+fn check_sentinel1_options(search_args: &mut SearchArgs) -> Result<(), Box<dyn Error>> {
+    if search_args.sentinel1.is_empty() {
+        return Ok(());
+    }
+    match &search_args.collections {
+        None => search_args.collections = Some(String::from("SENTINEL-1")),
+        Some(collections) if collections.split(',').all(|c| c.trim().eq_ignore_ascii_case("SENTINEL-1")) => (),
+        Some(collections) => {
+            return Err(format!("Sentinel-1 options can't be used to search {collections}: {}", search_args.sentinel1.in_use().join(", ")).into());
+        },
+    }
+    Ok(())
+}
+
+/*
+ * Looks products up by id, a batch at a time, since a catalogue request only
+ * takes so many. Ids not in the catalogue are left out.
+ */
+async fn lookup_products(client: &reqwest::Client, ids: &[String]) -> Result<Vec<Feature>, Box<dyn Error>> {
+    let mut features = Vec::new();
+    for chunk in ids.chunks(LOOKUP_SIZE) {
+        let query = QueryParams { ids: Some(chunk.join(",")), limit: Some(chunk.len() as u16), ..Default::default() };
+        features.extend(search_imagery(client, query).await?.features);
+    }
+    Ok(features)
+}
+
+// Files skipped without knowing their size might be left from an interrupted download
+fn format_unverified(item: &PlanItem) -> &'static str {
+    if item.unverified { " (size unknown, so unchecked. Delete it to download again)" } else { "" }
+}
+
+fn print_coverage(fc: &FeatureCollection, aoi: &Option<MultiPolygon>, search_args: &SearchArgs, display: &DisplayConfig) {
+    if !search_args.coverage {
+        return;
+    }
+    match aoi {
+        Some(aoi) => println!("\nCoverage:\n{}", format_coverage(&coverage_report(fc, aoi), display)),
+        None => println!("\nNo area of interest to report coverage for. Use --aoi, --bbox or --tile."),
+    }
+}
+
+/*
+ * Adding to, running and inspecting the download queue. Queue state is saved
+ * before and after each download, so killing a run loses nothing: the next one
+ * resumes the interrupted download and carries on.
+ */
+async fn manage_queue(
+    client: &reqwest::Client, profile: &Option<String>, config: &Config, token_store: &TokenStore, action: QueueAction
+) -> Result<(), Box<dyn Error>> {
+    let mut queue = Queue::load()?;
+    match action {
+        QueueAction::Add(add_args) => {
+            let ids = if add_args.products.is_empty() {
+                let (fc, _) = search(client, &add_args.search).await?;
+                fc.features.iter().filter_map(|f| util::get_id(&f.id)).collect()
+            } else {
+                add_args.products
+            };
+            let name_template = add_args.name_template.clone().or(config.name_template.clone());
+            let added = queue.add(&ids, &add_args.output_dir, &name_template)?;
+            queue.save()?;
+            println!("Queued {added} of {} product(s). {} pending.", ids.len(), queue.count(ItemState::Pending));
+        },
+        QueueAction::Run(run_args) => {
+            let recovered = queue.recover();
+            if recovered > 0 {
+                println!("Resuming {recovered} interrupted download(s).");
+            }
+            if run_args.retry_failed {
+                queue.retry_failed();
+            }
+            queue.save()?;
+            let pending = queue.pending();
+            if pending.is_empty() {
+                println!("Nothing pending in the queue.");
+                return Ok(());
+            }
+
+            let task = progress::task("Looking up queued products...");
+            let ids: Vec<String> = pending.iter().map(|i| i.id.clone()).collect();
+            let features = lookup_products(client, &ids).await?;
+            task.finish();
+
+            let downloads = download_client()?;
+            progress::batch_started(pending.len(), None);
+            for item in pending {
+                let feature = if let Some(f) = features.iter().find(|f| util::get_id(&f.id).as_ref() == Some(&item.id)) { f } else {
+                    queue.fail(&item.id, String::from("Not found in the catalogue"));
+                    queue.save()?;
+                    continue;
+                };
+                let plan = plan_downloads(client, std::slice::from_ref(feature), &item.output_dir, &item.name_template, false).await?;
+                if let Some(present) = plan.items.iter().find(|i| i.present) {
+                    println!("Already present: {}{}", present.destination.display(), format_unverified(present));
+                    queue.finish(&item.id, present.destination.clone());
+                    queue.save()?;
+                    continue;
+                }
+                if let Err(e) = plan.check_space() {
+                    queue.fail(&item.id, e.to_string());
+                    queue.save()?;
+                    continue;
+                }
+                if let Some(window) = &run_args.throttle.window {
+                    window.wait_until_open().await;
+                }
+                let auth_details = get_auth(profile, config, token_store).await?;
+
+                queue.start(&item.id);
+                queue.save()?;
+                let download = download_imagery(
+                    &downloads, &auth_details, feature, item.output_dir.clone(), &item.name_template,
+                    run_args.throttle.limit_rate, &config.trusted_hosts
+                );
+                match download.await {
+                    Ok(details) => {
+                        println!("{} bytes, saved to: {}", details.size, details.destination.display());
+                        queue.finish(&item.id, details.destination);
+                    },
+                    Err(e) => {
+                        progress::download_failed(&item.id, &e.to_string());
+                        queue.fail(&item.id, e.to_string());
+                    },
+                }
+                queue.save()?;
+            }
+            println!(
+                "{} done, {} failed, {} pending.",
+                queue.count(ItemState::Done), queue.count(ItemState::Failed), queue.count(ItemState::Pending)
+            );
+        },
+        QueueAction::Status => {
+            if queue.items.is_empty() {
+                println!("The queue is empty.");
+            } else {
+                println!("{}", format_queue(&queue, &config.display));
+                println!("Stored in {}", Queue::path()?.display());
+            }
+        },
+        QueueAction::Clear { all } => {
+            let removed = queue.clear(all);
+            queue.save()?;
+            println!("Removed {removed} item(s) from the queue.");
+        },
+    }
+    Ok(())
+}
+
+/*
+ * Resolves credentials for the selected profile, or from the environment.
+ */
+async fn get_credentials(profile: &Option<String>, config: &Config) -> Result<Credentials, Box<dyn Error>> {
+    let credentials = match profile {
+        Some(name) => get_profile_creds(name, find_profile(name, &config.profiles)?).await?,
+        None => get_env_creds(),
+    };
 
     // Check provided user name to see if it has a reasonable value, e.g. not
-    // the template value, and not None. If it doesn't, we can't auth. We could
-    // reauth within the refresh window with cached auth, but we can probably
-    // insist on this.
-    match credentials.user {
-        Some(ref user) => if user == "FAKE_USER" {
-            panic!("Template value present in env credentials. Check values?");
+    // the template value, and not None. If it doesn't, we can't auth. Service
+    // accounts go by their client id instead.
+    match (credentials.grant, &credentials.user, &credentials.client_id) {
+        (Grant::Password, Some(user), _) => if user == "FAKE_USER" {
+            return Err("Template value present in env credentials. Check values?".into());
         },
-        None => panic!("No env value for user. Check credentials.")
+        (Grant::Password, None, _) => return Err(format!("No user to authenticate as. Set {ENV_VAR_USER} or use --profile.").into()),
+        (Grant::ClientCredentials, _, Some(_)) => (),
+        (Grant::ClientCredentials, _, None) => return Err("Profiles using client credentials need a client_id.".into()),
     }
+    Ok(credentials)
+}
 
-    let client = reqwest::Client::new();
+/*
+ * Gets working auth for commands that need it, reusing or refreshing stored
+ * tokens where possible. Credentials are only resolved if we have to log in again,
+ * so a still-valid session doesn't, e.g., run a password command.
+ */
+async fn get_auth(profile: &Option<String>, config: &Config, token_store: &TokenStore) -> Result<AuthDetails, Box<dyn Error>> {
+    let token_profile = profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+    let stored = token_store.load(token_profile).await?;
+    let credentials = if needs_credentials(&stored) {
+        get_credentials(profile, config).await?
+    } else {
+        Credentials::default()
+    };
 
     info!("Checking auth...");
-    let auth_details = check_auth(config.auth_details, &credentials).await?;
+    let auth_details = check_auth(stored, &credentials).await?;
     info!("Auth ok!");
 
     // Save auth details
-    config.auth_details = Some(auth_details.clone());
-    confy::store(APP_NAME, None, config)?;
+    token_store.save(token_profile, &auth_details).await?;
+    Ok(auth_details)
+}
 
-    match args.mode {
-        Mode::Search(search_args) => {
-            let mut s = Spinner::new(Spinners::Dots, "Searching for imagery...".into());
-            let fc = search_imagery(&client, &auth_details, search_args.into()).await?;
-            s.stop_with_newline();
-            println!("Search results:\n{}", format_feature_collection(&fc));
-            Ok(())
+/*
+ * Saving and removing passwords for a profile.
+ */
+async fn manage_credentials(profile: &Option<String>, config: &Config, action: CredentialsAction) -> Result<(), Box<dyn Error>> {
+    let name = if let Some(name) = profile { name } else {
+        return Err("Saving or removing a password requires --profile".into());
+    };
+    let profile = find_profile(name, &config.profiles)?;
+    match action {
+        CredentialsAction::Set { allow_file } => {
+            let pass = rpassword::prompt_password(format!("Password for '{name}': "))?;
+            let location = store_password(name, profile, &pass, allow_file).await?;
+            println!("Password saved to {}", location.display());
         },
-        Mode::Download(download_args) => {
-            let mut s = Spinner::new(Spinners::Dots, "Querying for imagery with id...".into());
-            let fc = search_imagery(&client, &auth_details, download_args.clone().into()).await?;
-            s.stop_with_newline();
-            if fc.features.is_empty() {
-                return Err(format!("No imagery found for id: {:?}", download_args.ids).into());
-            }
-            let details = download_imagery(&client, &auth_details, &fc.features[0], download_args.output_dir).await?;
-            println!("{} bytes, saved to: {}", details.size, details.destination.to_str().unwrap_or("_"));
-            Ok(())
+        CredentialsAction::Remove => {
+            remove_password(name).await?;
+            println!("Password removed for '{name}'");
         },
     }
+    Ok(())
 }
 
+/*
+ * Inspecting, creating and ending stored sessions.
+ */
+async fn manage_auth(profile: &Option<String>, config: &Config, token_store: &TokenStore, action: AuthAction) -> Result<(), Box<dyn Error>> {
+    let token_profile = profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+    match action {
+        AuthAction::Status => {
+            let tokens = token_store.load_all().await?;
+            if tokens.is_empty() {
+                println!("No stored tokens.");
+            } else {
+                let mut profiles: Vec<&String> = tokens.keys().collect();
+                profiles.sort();
+                for profile in profiles {
+                    println!("{}", format_token_status(profile, &tokens[profile]));
+                }
+                let encryption = if token_store.encrypt { "encrypted" } else { "not encrypted" };
+                println!("Stored in {} ({encryption})", token_store.path()?.display());
+            }
+        },
+        AuthAction::Login => {
+            let credentials = get_credentials(profile, config).await?;
+            let auth_details = authenticate_credentials(&credentials).await?;
+            token_store.save(token_profile, &auth_details).await?;
+            println!("{}", format_token_status(token_profile, &auth_details));
+        },
+        AuthAction::Logout { all } => {
+            let removed = token_store.remove(if all { None } else { Some(token_profile) }).await?;
+            for auth_details in &removed {
+                if let Err(e) = revoke_authentication(auth_details).await {
+                    info!("Unable to end session on the server: {e}");
+                }
+            }
+            println!("Removed {} stored session(s).", removed.len());
+        },
+    }
+    Ok(())
+}
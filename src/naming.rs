@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use chrono::DateTime;
+use chrono::format::{Item, StrftimeItems};
+use geojson::Feature;
+use log::debug;
+use serde::{Serialize, Deserialize};
+
+use crate::util::{collection_of, config_dir, get_id, get_value, write_private};
+
+// Kept next to the config, like the queue
+const NAMES_FILE: &str = "names.json";
+
+/*
+ * Where downloads are written. By default products keep the name the server
+ * gives them, and a name template lays them out by feature properties instead,
+ * e.g. {collection}/{date:%Y/%m}/{tileId}/{id}.zip. The server's name is only
+ * known once a download starts, so until then products are written as <id>.zip
+ * (.part), and the names the server gave are recorded, so plans, the queue and
+ * later downloads look for the same file.
+ */
+
+/*
+ * Fills in a name template for a feature. Placeholders are {id}, {collection},
+ * {date} (formatted with a chrono format after a colon, %F by default) and any
+ * feature property by name. Values are made safe as path components, so only the
+ * template's own slashes make directories.
+ */
+pub fn render_name_template(template: &str, feature: &Feature) -> Result<PathBuf, Box<dyn Error>> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let close = rest[open..].find('}').ok_or(format!("Unclosed {{ in name template: {template}"))? + open;
+        rendered.push_str(&rest[..open]);
+        rendered.push_str(&placeholder_value(&rest[open + 1..close], feature)?);
+        rest = &rest[close + 1..];
+    }
+    rendered.push_str(rest);
+
+    let path = PathBuf::from(rendered);
+    if path.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(format!("Name templates must give relative paths without '..': {}", path.display()).into());
+    }
+    Ok(path)
+}
+
+fn placeholder_value(placeholder: &str, feature: &Feature) -> Result<String, Box<dyn Error>> {
+    let (name, format) = match placeholder.split_once(':') {
+        Some((name, format)) => (name, Some(format)),
+        None => (placeholder, None),
+    };
+    // chrono panics when formatting with a bad specifier, so check it first
+    if let Some(format) = format.filter(|f| name == "date" && StrftimeItems::new(f).any(|item| matches!(item, Item::Error))) {
+        return Err(format!("Invalid date format in name template: {format}").into());
+    }
+    let property = |name: &str| feature.properties.as_ref().and_then(|p| p.get(name).cloned());
+    let value = match name {
+        "id" => get_id(&feature.id),
+        "collection" => Some(collection_of(feature)).filter(|c| !c.is_empty()),
+        "date" => property("datetime")
+            .and_then(|v| v.as_str().and_then(|d| DateTime::parse_from_rfc3339(d).ok()))
+            // Formatting the date can make directories, e.g. %Y/%m, so it isn't sanitized
+            .map(|d| d.format(format.unwrap_or("%F")).to_string()),
+        other => property(other).and_then(|v| get_value(Some(v))).map(|v| sanitize(&v)),
+    };
+    match value {
+        Some(v) if !v.is_empty() && v != "N/A" => Ok(v),
+        _ => {
+            debug!("Naming: no value for {{{placeholder}}}");
+            Ok(String::from("unknown"))
+        }
+    }
+}
+
+// Keeps a value to a single path component
+fn sanitize(value: &str) -> String {
+    let cleaned: String = value.chars().map(|c| if matches!(c, '/' | '\\' | ':') || c.is_control() { '_' } else { c }).collect();
+    if cleaned == "." || cleaned == ".." { cleaned.replace('.', "_") } else { cleaned }
+}
+
+/*
+ * Pulls the filename from a content-disposition header, either filename="x",
+ * filename=x, or the RFC 5987 filename*=UTF-8''x form. Anything that isn't a
+ * plain file name, e.g. with a path in it, is ignored.
+ */
+pub fn disposition_filename(header: &str) -> Option<String> {
+    let mut filename: Option<String> = None;
+    for part in header.split(';').map(str::trim) {
+        if let Some(value) = part.strip_prefix("filename*=") {
+            // Preferred over plain filename when both are given
+            let encoded = value.rsplit('\'').next().unwrap_or(value);
+            return Some(percent_decode(encoded)).filter(|n| is_plain_file_name(n));
+        } else if let Some(value) = part.strip_prefix("filename=") {
+            filename = Some(value.trim_matches('"').to_string());
+        }
+    }
+    filename.filter(|n| is_plain_file_name(n))
+}
+
+fn percent_decode(s: &str) -> String {
+    url::form_urlencoded::parse(format!("n={}", s.replace('+', "%2B")).as_bytes())
+        .next()
+        .map(|(_, v)| v.to_string())
+        .unwrap_or(s.to_string())
+}
+
+fn is_plain_file_name(name: &str) -> bool {
+    let path = Path::new(name);
+    !name.is_empty() && path.components().count() == 1 && matches!(path.components().next(), Some(Component::Normal(_)))
+}
+
+/*
+ * Where a product is written before the server's name for it is known: the
+ * template's path, or <id>.zip. Partial downloads are kept next to this, so they
+ * can be resumed whatever the finished file ends up being called.
+ */
+pub fn initial_path(feature: &Feature, output_dir: &Option<String>, name_template: &Option<String>) -> Result<PathBuf, Box<dyn Error>> {
+    let dir = PathBuf::from(output_dir.clone().unwrap_or(String::from("./")));
+    match name_template {
+        Some(template) => Ok(dir.join(render_name_template(template, feature)?)),
+        None => Ok(dir.join(format!("{}.zip", feature_id(feature)?))),
+    }
+}
+
+/*
+ * Where a product ends up: the template's path, or the name the server gave it
+ * when it was last downloaded, or <id>.zip if it hasn't been.
+ */
+pub fn planned_path(feature: &Feature, output_dir: &Option<String>, name_template: &Option<String>) -> Result<PathBuf, Box<dyn Error>> {
+    let path = initial_path(feature, output_dir, name_template)?;
+    if name_template.is_some() {
+        return Ok(path);
+    }
+    match ServerNames::load()?.names.get(&feature_id(feature)?) {
+        Some(name) => Ok(path.with_file_name(name)),
+        None => Ok(path),
+    }
+}
+
+fn feature_id(feature: &Feature) -> Result<String, Box<dyn Error>> {
+    get_id(&feature.id).ok_or(format!("Feature without an id: {:?}", feature.id).into())
+}
+
+/*
+ * File names the server gave products, by id, kept next to the config. Only the
+ * name is kept, so it applies to whichever output directory a product is in.
+ */
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ServerNames {
+    pub names: HashMap<String, String>,
+}
+
+impl ServerNames {
+    pub fn path() -> Result<PathBuf, Box<dyn Error>> {
+        Ok(config_dir()?.join(NAMES_FILE))
+    }
+
+    pub fn load() -> Result<ServerNames, Box<dyn Error>> {
+        let path = ServerNames::path()?;
+        if !path.exists() {
+            return Ok(ServerNames::default());
+        }
+        let contents = fs::read(&path)?;
+        serde_json::from_slice(&contents).map_err(|e| format!("Unable to read server names {}: {e}", path.display()).into())
+    }
+
+    // Records the name the server gave a product
+    pub fn record(id: &str, name: &str) -> Result<(), Box<dyn Error>> {
+        let mut server_names = ServerNames::load()?;
+        if server_names.names.get(id).map(String::as_str) != Some(name) {
+            server_names.names.insert(id.to_string(), name.to_string());
+            write_private(&ServerNames::path()?, &serde_json::to_vec_pretty(&server_names)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feature() -> Feature {
+        let json = r#"{"type":"Feature","id":"S2A_T18TVK","geometry":null,"properties":{"datetime":"2024-06-04T15:49:11Z","tileId":"18TVK"}}"#;
+        Feature::from_json_value(serde_json::from_str(json).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn renders_templates() {
+        let path = render_name_template("{date:%Y/%m}/{tileId}/{id}.zip", &feature()).unwrap();
+        assert_eq!(path, PathBuf::from("2024/06/18TVK/S2A_T18TVK.zip"));
+        let path = render_name_template("{date}_{missing}.zip", &feature()).unwrap();
+        assert_eq!(path, PathBuf::from("2024-06-04_unknown.zip"));
+    }
+
+    #[test]
+    fn refuses_invalid_date_formats() {
+        let error = render_name_template("{date:%Q}/{id}.zip", &feature()).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid date format in name template: %Q");
+    }
+
+    #[test]
+    fn refuses_paths_leaving_the_output_directory() {
+        assert!(render_name_template("../{id}.zip", &feature()).is_err());
+        assert!(render_name_template("/tmp/{id}.zip", &feature()).is_err());
+    }
+}
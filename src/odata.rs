@@ -0,0 +1,434 @@
+use std::collections::HashSet;
+use std::error::Error;
+
+use chrono::{DateTime, Utc};
+use chrono::SecondsFormat::Millis;
+use geo::{BoundingRect, Geometry as GeoGeometry};
+use geojson::{Feature, FeatureCollection, Geometry};
+use log::info;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use crate::api::QueryParams;
+use crate::util::{get_id, parse_bbox};
+
+// GET
+const PRODUCTS_URL: &str = "https://catalogue.dataspace.copernicus.eu/odata/v1/Products";
+// Products are downloaded from their entry, like the STAC PRODUCT asset
+const PRODUCT_VALUE_URL: &str = "https://catalogue.dataspace.copernicus.eu/odata/v1/Products({id})/$value";
+// Products per page when no limit is given, as the catalogue does
+const DEFAULT_TOP: u16 = 20;
+// Products per request when paging through everything published since a date
+const PAGE_SIZE: u16 = 100;
+
+/*
+ * The OData catalogue. It filters on things STAC can't, e.g. parts of product
+ * names, whether products are online and any product attribute, and its products
+ * are turned into the same features STAC gives, so everything after the search
+ * works the same either way.
+ */
+
+#[derive(Deserialize, Debug)]
+struct ProductsResponse {
+    value: Vec<Product>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Product {
+    id: String,
+    name: String,
+    content_length: Option<u64>,
+    publication_date: Option<String>,
+    online: Option<bool>,
+    content_date: Option<ContentDate>,
+    geo_footprint: Option<Geometry>,
+    #[serde(default)]
+    attributes: Vec<Attribute>,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ContentDate {
+    start: Option<String>,
+    end: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Attribute {
+    name: String,
+    value: Value,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct Asset {
+    #[serde(rename = "Type")]
+    asset_type: String,
+    download_link: Option<String>,
+}
+
+/*
+ * Searches products. Searches by publication date are there to catch everything
+ * new, so they're sorted by it and paged through until the catalogue runs out, or
+ * the limit is reached, rather than stopping after a page.
+ */
+pub async fn search_products(client: &Client, query_params: &QueryParams) -> Result<FeatureCollection, Box<dyn Error>> {
+    let mut fc = FeatureCollection { bbox: None, features: Vec::new(), foreign_members: None };
+    if query_params.published_since.is_none() || query_params.page.is_some() {
+        // Pages count from 1, as they do for STAC, and are as long as the limit
+        let top = query_params.limit.unwrap_or(DEFAULT_TOP);
+        let skip = (query_params.page.unwrap_or(1).max(1) as u64 - 1) * top as u64;
+        fc.features = fetch_products(client, query_params, top, skip).await?;
+        return Ok(fc);
+    }
+    // The catalogue refuses large $skip values, so each page starts from the last
+    // publication date seen instead. Products published at that same moment come
+    // back again, and are left out by id.
+    let mut page_params = query_params.clone();
+    let mut seen: HashSet<String> = HashSet::new();
+    loop {
+        let wanted = query_params.limit.map(|l| l as usize - fc.features.len());
+        let top = wanted.map_or(PAGE_SIZE, |w| w.min(PAGE_SIZE as usize) as u16);
+        if top == 0 {
+            break;
+        }
+        let products = fetch_products(client, &page_params, top, 0).await?;
+        let last_page = products.len() < top as usize;
+        let last_published = products.last().and_then(publication_date);
+        let before = fc.features.len();
+        for product in products {
+            if get_id(&product.id).is_none_or(|id| seen.insert(id)) {
+                fc.features.push(product);
+            }
+        }
+        // A page with nothing new would just be asked for again
+        if last_page || fc.features.len() == before || last_published.is_none() {
+            break;
+        }
+        page_params.published_since = last_published;
+    }
+    Ok(fc)
+}
+
+fn publication_date(feature: &Feature) -> Option<DateTime<Utc>> {
+    let date = feature.properties.as_ref()?.get("publicationDate")?.as_str()?;
+    DateTime::parse_from_rfc3339(date).ok().map(|d| d.with_timezone(&Utc))
+}
+
+async fn fetch_products(client: &Client, query_params: &QueryParams, top: u16, skip: u64) -> Result<Vec<Feature>, Box<dyn Error>> {
+    let mut url: Url = Url::parse(PRODUCTS_URL)?;
+    {
+        let mut query = url.query_pairs_mut();
+        let filter = generate_filter(query_params)?;
+        if !filter.is_empty() {
+            query.append_pair("$filter", &filter);
+        }
+        if query_params.published_since.is_some() {
+            // Keeps pages stable while paging, and the newest last
+            query.append_pair("$orderby", "PublicationDate asc");
+        } else if let Some(orderby) = query_params.sortby.as_deref().map(generate_orderby) {
+            query.append_pair("$orderby", &orderby?);
+        }
+        query.append_pair("$top", &top.to_string());
+        if skip > 0 {
+            query.append_pair("$skip", &skip.to_string());
+        }
+        query.append_pair("$expand", "Attributes,Assets");
+    }
+
+    info!("OData::search_products: Requesting {url}...");
+    let response = client.get(url).send().await?;
+    let status = response.status();
+    let response_text = response.text().await.unwrap_or_default();
+    info!("OData::search_products: Response: \n{response_text}");
+    if !status.is_success() {
+        return Err(format!("OData search failed ({status}): {response_text}").into());
+    }
+    let products: ProductsResponse = serde_json::from_str(&response_text)?;
+    Ok(products.value.into_iter().map(product_feature).collect())
+}
+
+/*
+ * Builds $filter from the query. Values are quoted OData strings, so quotes in
+ * them are doubled.
+ */
+fn generate_filter(query_params: &QueryParams) -> Result<String, Box<dyn Error>> {
+    let mut filters: Vec<String> = Vec::new();
+
+    if let Some(ids) = &query_params.ids {
+        filters.push(any_of(ids, |id| format!("Name eq {}", quote(id))));
+    }
+
+    if let Some(collections) = &query_params.collections {
+        filters.push(any_of(collections, |c| format!("Collection/Name eq {}", quote(c))));
+    }
+
+    if let Some(bbox) = &query_params.bbox {
+        let [min_x, min_y, max_x, max_y] = parse_bbox(bbox).map_err(|e| e.to_string())?;
+        filters.push(format!(
+            "OData.CSC.Intersects(area=geography'SRID=4326;POLYGON(({min_x} {min_y},{max_x} {min_y},{max_x} {max_y},{min_x} {max_y},{min_x} {min_y}))')"
+        ));
+    }
+
+    if let Some(from) = query_params.from {
+        filters.push(format!("ContentDate/Start ge {}", from.to_rfc3339_opts(Millis, true)));
+    }
+
+    if let Some(to) = query_params.to {
+        filters.push(format!("ContentDate/Start le {}", to.to_rfc3339_opts(Millis, true)));
+    }
+
+    if let Some(published_since) = query_params.published_since {
+        filters.push(format!("PublicationDate ge {}", published_since.to_rfc3339_opts(Millis, true)));
+    }
+
+    if let Some(name) = &query_params.name {
+        filters.push(format!("contains(Name,{})", quote(name)));
+    }
+
+    if query_params.online {
+        filters.push(String::from("Online eq true"));
+    }
+
+    for (name, value) in &query_params.attributes {
+        filters.push(attribute_filter(name, value));
+    }
+
+    if !query_params.tiles.is_empty() {
+        filters.push(any_of(&query_params.tiles.join(","), |tile| attribute_filter("tileId", tile)));
+    }
+
+    if !query_params.relative_orbits.is_empty() {
+        let orbits: Vec<String> = query_params.relative_orbits.iter().map(|o| o.to_string()).collect();
+        filters.push(any_of(&orbits.join(","), |orbit| attribute_filter("relativeOrbitNumber", orbit)));
+    }
+
+    let sentinel1 = &query_params.sentinel1;
+    if let Some(polarisation) = &sentinel1.polarisation {
+        filters.push(attribute_filter("polarisationChannels", &polarisation.catalogue_value()));
+    }
+    if let Some(direction) = &sentinel1.orbit_direction {
+        filters.push(attribute_filter("orbitDirection", direction.catalogue_value()));
+    }
+    if let Some(mode) = &sentinel1.sensor_mode {
+        filters.push(attribute_filter("operationalMode", mode.catalogue_value()));
+    }
+    // productType has the mode and resolution too, e.g. IW_GRDH_1S, but names
+    // always have the type after an underscore, e.g. S1A_IW_GRDH_1SDV_...
+    if let Some(product_type) = &sentinel1.product_type {
+        filters.push(format!("contains(Name,{})", quote(&format!("_{}", product_type.catalogue_value()))));
+    }
+
+    Ok(filters.join(" and "))
+}
+
+// Comma separated values, any of which can match
+fn any_of(values: &str, filter: impl Fn(&str) -> String) -> String {
+    let parts: Vec<String> = values.split(',').map(str::trim).filter(|v| !v.is_empty()).map(filter).collect();
+    if parts.len() == 1 { parts[0].clone() } else { format!("({})", parts.join(" or ")) }
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/*
+ * Attributes are typed, so the filter has to say which type it's looking for.
+ * Numbers could be either integers or doubles, e.g. cloudCover is a double even
+ * when it's 0, so they're looked for as both. Anything else is a string.
+ */
+fn attribute_filter(name: &str, value: &str) -> String {
+    let typed = |attribute_type: &str, value: &str| format!(
+        "Attributes/OData.CSC.{attribute_type}/any(att:att/Name eq {} and att/OData.CSC.{attribute_type}/Value eq {value})",
+        quote(name)
+    );
+    if value.parse::<i64>().is_ok() {
+        format!("({} or {})", typed("IntegerAttribute", value), typed("DoubleAttribute", value))
+    } else if value.parse::<f64>().is_ok() {
+        typed("DoubleAttribute", value)
+    } else {
+        typed("StringAttribute", &quote(value))
+    }
+}
+
+/*
+ * STAC's sortby, e.g. -datetime, as $orderby. OData products only sort by
+ * their own fields, so only dates are supported.
+ */
+fn generate_orderby(sortby: &str) -> Result<String, Box<dyn Error>> {
+    let (field, direction) = match sortby.strip_prefix('-') {
+        Some(field) => (field, "desc"),
+        None => (sortby.trim_start_matches('+'), "asc"),
+    };
+    let field = match field {
+        "datetime" | "start_datetime" => "ContentDate/Start",
+        "end_datetime" => "ContentDate/End",
+        other => return Err(format!("OData searches can only be sorted by datetime, start_datetime or end_datetime, not {other}").into()),
+    };
+    Ok(format!("{field} {direction}"))
+}
+
+/*
+ * Turns a product into a feature shaped like STAC's: the product name as the id,
+ * attributes as properties, the content dates as datetimes, and the product and
+ * quicklook as assets.
+ */
+fn product_feature(product: Product) -> Feature {
+    let mut properties = Map::new();
+    for attribute in product.attributes {
+        properties.insert(attribute.name, attribute.value);
+    }
+    if let Some(content_date) = product.content_date {
+        if let Some(start) = content_date.start {
+            properties.insert(String::from("datetime"), Value::String(start.clone()));
+            properties.insert(String::from("start_datetime"), Value::String(start));
+        }
+        if let Some(end) = content_date.end {
+            properties.insert(String::from("end_datetime"), Value::String(end));
+        }
+    }
+    if let Some(published) = product.publication_date {
+        properties.insert(String::from("publicationDate"), Value::String(published));
+    }
+    if let Some(online) = product.online {
+        properties.insert(String::from("online"), Value::Bool(online));
+    }
+
+    let mut assets = Map::new();
+    let mut product_asset = json!({
+        "href": PRODUCT_VALUE_URL.replace("{id}", &product.id),
+        "title": "Product",
+        "type": "application/octet-stream",
+    });
+    if let Some(length) = product.content_length {
+        product_asset["file:size"] = json!(length);
+    }
+    assets.insert(String::from("PRODUCT"), product_asset);
+    for asset in product.assets {
+        if let Some(link) = asset.download_link {
+            assets.insert(asset.asset_type.to_uppercase(), json!({ "href": link, "title": asset.asset_type }));
+        }
+    }
+
+    let mut foreign_members = Map::new();
+    if let Some(platform) = properties.get("platformShortName").cloned() {
+        foreign_members.insert(String::from("collection"), platform);
+    }
+    foreign_members.insert(String::from("assets"), Value::Object(assets));
+
+    let bbox = product.geo_footprint.as_ref()
+        .and_then(|g| GeoGeometry::<f64>::try_from(g.value.clone()).ok())
+        .and_then(|g| g.bounding_rect())
+        .map(|r| vec![r.min().x, r.min().y, r.max().x, r.max().y]);
+
+    Feature {
+        bbox,
+        geometry: product.geo_footprint,
+        id: Some(geojson::feature::Id::String(product.name)),
+        properties: Some(properties),
+        foreign_members: Some(foreign_members),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::sentinel1::{OrbitDirection, ProductType, Sentinel1Args, parse_polarisation};
+
+    #[test]
+    fn quote_doubles_single_quotes() {
+        assert_eq!(quote("S2A"), "'S2A'");
+        assert_eq!(quote("it's"), "'it''s'");
+    }
+
+    #[test]
+    fn attribute_filter_types_values() {
+        assert_eq!(
+            attribute_filter("productType", "S2MSI2A"),
+            "Attributes/OData.CSC.StringAttribute/any(att:att/Name eq 'productType' and att/OData.CSC.StringAttribute/Value eq 'S2MSI2A')"
+        );
+        assert_eq!(
+            attribute_filter("cloudCover", "12.5"),
+            "Attributes/OData.CSC.DoubleAttribute/any(att:att/Name eq 'cloudCover' and att/OData.CSC.DoubleAttribute/Value eq 12.5)"
+        );
+        let integer = attribute_filter("relativeOrbitNumber", "54");
+        assert!(integer.starts_with("(Attributes/OData.CSC.IntegerAttribute/any("));
+        assert!(integer.contains(" or Attributes/OData.CSC.DoubleAttribute/any("));
+    }
+
+    #[test]
+    fn attribute_filter_quotes_names_and_values() {
+        let filter = attribute_filter("o'name", "a'b");
+        assert!(filter.contains("att/Name eq 'o''name'"));
+        assert!(filter.ends_with("Value eq 'a''b')"));
+    }
+
+    #[test]
+    fn orderby_maps_dates() {
+        assert_eq!(generate_orderby("-datetime").unwrap(), "ContentDate/Start desc");
+        assert_eq!(generate_orderby("+end_datetime").unwrap(), "ContentDate/End asc");
+        assert_eq!(generate_orderby("start_datetime").unwrap(), "ContentDate/Start asc");
+        assert!(generate_orderby("cloudCover").is_err());
+    }
+
+    #[test]
+    fn filter_is_empty_without_options() {
+        assert_eq!(generate_filter(&QueryParams::default()).unwrap(), "");
+    }
+
+    #[test]
+    fn filter_joins_options() {
+        let query_params = QueryParams {
+            ids: Some(String::from("a, b")),
+            collections: Some(String::from("SENTINEL-2")),
+            bbox: Some(String::from("1,2,3,4")),
+            from: Some(Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap()),
+            name: Some(String::from("_T18TVK_")),
+            online: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            generate_filter(&query_params).unwrap(),
+            "(Name eq 'a' or Name eq 'b') and Collection/Name eq 'SENTINEL-2' and \
+             OData.CSC.Intersects(area=geography'SRID=4326;POLYGON((1 2,3 2,3 4,1 4,1 2))') and \
+             ContentDate/Start ge 2024-06-01T00:00:00.000Z and contains(Name,'_T18TVK_') and Online eq true"
+        );
+    }
+
+    #[test]
+    fn filter_sends_tiles_orbits_and_sentinel1_options() {
+        let query_params = QueryParams {
+            tiles: Vec::from([String::from("18TVK"), String::from("18TWK")]),
+            relative_orbits: Vec::from([54]),
+            sentinel1: Sentinel1Args {
+                polarisation: Some(parse_polarisation("VH+VV").unwrap()),
+                orbit_direction: Some(OrbitDirection::Ascending),
+                product_type: Some(ProductType::Grd),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let filter = generate_filter(&query_params).unwrap();
+        assert!(filter.starts_with(&format!(
+            "({} or {})", attribute_filter("tileId", "18TVK"), attribute_filter("tileId", "18TWK")
+        )));
+        assert!(filter.contains(&attribute_filter("relativeOrbitNumber", "54")));
+        assert!(filter.contains(&attribute_filter("polarisationChannels", "VV&VH")));
+        assert!(filter.contains(&attribute_filter("orbitDirection", "ASCENDING")));
+        assert!(filter.ends_with("contains(Name,'_GRD')"));
+    }
+
+    #[test]
+    fn filter_refuses_bad_bbox() {
+        let query_params = QueryParams { bbox: Some(String::from("1,2,3")), ..Default::default() };
+        assert!(generate_filter(&query_params).is_err());
+    }
+}
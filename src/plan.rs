@@ -0,0 +1,117 @@
+use std::error::Error;
+use std::fs::metadata;
+use std::path::{Path, PathBuf};
+
+use geojson::Feature;
+use log::debug;
+use reqwest::Client;
+
+use crate::api::fetch_product_size;
+use crate::naming::planned_path;
+use crate::table::product_size;
+use crate::util::{format_size, get_id};
+
+/*
+ * What a batch download would do, worked out before fetching anything: where
+ * each product goes, how big it is, and whether it's already there.
+ */
+#[derive(Debug)]
+pub struct DownloadPlan {
+    pub items: Vec<PlanItem>,
+    pub directory: PathBuf,
+    // Space available to us in the output directory, where the OS says
+    pub free_space: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct PlanItem {
+    pub id: String,
+    pub feature: Feature,
+    pub destination: PathBuf,
+    pub size: Option<u64>,
+    pub present: bool,
+    // Present, but with no size to check it against, so it may be incomplete
+    pub unverified: bool,
+}
+
+impl DownloadPlan {
+    // Items that still need downloading
+    pub fn pending(&self) -> impl Iterator<Item = &PlanItem> {
+        self.items.iter().filter(|i| !i.present)
+    }
+
+    // Bytes to download, counting only items with a known size
+    pub fn total_bytes(&self) -> u64 {
+        self.pending().filter_map(|i| i.size).sum()
+    }
+
+    pub fn unknown_sizes(&self) -> usize {
+        self.pending().filter(|i| i.size.is_none()).count()
+    }
+
+    /*
+     * Fails if the known sizes alone won't fit in the output directory. Unknown
+     * sizes can't be checked, so those may still run out of space.
+     */
+    pub fn check_space(&self) -> Result<(), Box<dyn Error>> {
+        match self.free_space {
+            Some(free) if free < self.total_bytes() => Err(format!(
+                "Not enough space in {}: {} needed, {} free",
+                self.directory.display(), format_size(self.total_bytes()), format_size(free)
+            ).into()),
+            _ => Ok(())
+        }
+    }
+}
+
+/*
+ * Plans downloads of features into output_dir. Sizes come from the search
+ * results where the catalogue includes them, and are looked up otherwise, which
+ * takes a request or two per product. Without lookup_sizes, e.g. when about to
+ * download anyway, they're only looked up to check files already there. Files
+ * present with the expected size are skipped. Ones whose size can't be found
+ * are skipped too, but marked unverified so they can be reported.
+ */
+pub async fn plan_downloads(
+    client: &Client, features: &[Feature], output_dir: &Option<String>, name_template: &Option<String>, lookup_sizes: bool
+) -> Result<DownloadPlan, Box<dyn Error>> {
+    let mut items: Vec<PlanItem> = Vec::new();
+    for feature in features {
+        let id = get_id(&feature.id).ok_or(format!("Feature without an id: {feature:?}"))?;
+        let destination = planned_path(feature, output_dir, name_template)?;
+        let existing = metadata(&destination).ok().filter(|m| m.is_file());
+        let size = match product_size(feature) {
+            Some(size) => Some(size),
+            None if lookup_sizes || existing.is_some() => fetch_product_size(client, feature).await,
+            None => None,
+        };
+        let present = match (&existing, size) {
+            (Some(m), Some(size)) => m.len() == size,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        let unverified = present && size.is_none();
+        debug!("Plan: {id} size {size:?}, present: {present}, unverified: {unverified}");
+        items.push(PlanItem { id, feature: feature.clone(), destination, size, present, unverified });
+    }
+    let directory = PathBuf::from(output_dir.clone().unwrap_or(String::from("./")));
+    let free_space = free_space(&directory);
+    Ok(DownloadPlan { items, directory, free_space })
+}
+
+/*
+ * Space available to unprivileged users on the filesystem holding dir. Output
+ * directories may not exist yet, so this looks at the nearest one that does.
+ */
+#[cfg(unix)]
+fn free_space(dir: &Path) -> Option<u64> {
+    let existing = dir.ancestors().find(|d| d.as_os_str().is_empty() || d.exists())?;
+    let existing = if existing.as_os_str().is_empty() { Path::new(".") } else { existing };
+    let stats = rustix::fs::statvfs(existing).ok()?;
+    Some(stats.f_bavail * stats.f_frsize)
+}
+
+#[cfg(not(unix))]
+fn free_space(_dir: &Path) -> Option<u64> {
+    None
+}
@@ -0,0 +1,158 @@
+use std::error::Error;
+use std::fs;
+use std::io::{Read, stdin};
+
+use geojson::{Feature, GeoJson};
+use serde_json::Value;
+
+use crate::util::{from_path, get_id};
+
+/*
+ * Products to download, read from a file or stdin. Features that come with a
+ * product link, e.g. piped from search, are used as they are, and anything else
+ * only gives an id to look up.
+ */
+#[derive(Debug, Default)]
+pub struct ProductList {
+    pub features: Vec<Feature>,
+    pub ids: Vec<String>,
+}
+
+impl ProductList {
+    fn add_feature(&mut self, feature: Feature) -> Result<(), Box<dyn Error>> {
+        let id = get_id(&feature.id).ok_or("Listed feature has no id")?;
+        if self.contains(&id) {
+            return Ok(());
+        }
+        if from_path(Vec::from(["assets", "PRODUCT", "href"]), &feature.foreign_members).is_some() {
+            self.features.push(feature);
+        } else {
+            self.ids.push(id);
+        }
+        Ok(())
+    }
+
+    pub fn add_id(&mut self, id: &str) {
+        if !self.contains(id) {
+            self.ids.push(id.to_string());
+        }
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.ids.iter().any(|i| i == id) || self.features.iter().any(|f| get_id(&f.id).as_deref() == Some(id))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty() && self.ids.is_empty()
+    }
+}
+
+// Reads a list from a file, or from stdin given -
+pub fn read_product_list(source: &str, list: &mut ProductList) -> Result<(), Box<dyn Error>> {
+    let contents = if source == "-" {
+        let mut contents = String::new();
+        stdin().read_to_string(&mut contents)?;
+        contents
+    } else {
+        fs::read_to_string(source).map_err(|e| format!("Unable to read {source}: {e}"))?
+    };
+    parse_product_list(&contents, list).map_err(|e| format!("Unable to read products from {source}: {e}").into())
+}
+
+/*
+ * Takes a GeoJSON FeatureCollection or Feature, a feature per line (NDJSON), or
+ * ids separated by commas, spaces or lines. Lines starting with # are comments.
+ */
+fn parse_product_list(contents: &str, list: &mut ProductList) -> Result<(), Box<dyn Error>> {
+    if let Ok(geojson) = contents.parse::<GeoJson>() {
+        return match geojson {
+            GeoJson::FeatureCollection(fc) => fc.features.into_iter().try_for_each(|f| list.add_feature(f)),
+            GeoJson::Feature(f) => list.add_feature(f),
+            GeoJson::Geometry(_) => Err("Expected features, not a geometry".into()),
+        };
+    }
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('{') {
+            let value: Value = serde_json::from_str(line).map_err(|e| format!("line {}: {e}", number + 1))?;
+            let feature = Feature::from_json_value(value).map_err(|e| format!("line {}: {e}", number + 1))?;
+            list.add_feature(feature)?;
+        } else {
+            line.split(|c: char| c == ',' || c.is_whitespace()).filter(|id| !id.is_empty()).for_each(|id| list.add_id(id));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRODUCT: &str = r#""assets":{"PRODUCT":{"href":"https://example.com/a/$value"}}"#;
+
+    fn parse(contents: &str) -> ProductList {
+        let mut list = ProductList::default();
+        parse_product_list(contents, &mut list).unwrap();
+        list
+    }
+
+    fn feature_ids(list: &ProductList) -> Vec<String> {
+        list.features.iter().filter_map(|f| get_id(&f.id)).collect()
+    }
+
+    #[test]
+    fn reads_feature_collections() {
+        let list = parse(&format!(
+            r#"{{"type":"FeatureCollection","features":[
+                {{"type":"Feature","id":"a","geometry":null,"properties":{{}},{PRODUCT}}},
+                {{"type":"Feature","id":"b","geometry":null,"properties":{{}}}}
+            ]}}"#
+        ));
+        // Only features with a product link can be downloaded as they are
+        assert_eq!(feature_ids(&list), ["a"]);
+        assert_eq!(list.ids, ["b"]);
+    }
+
+    #[test]
+    fn reads_ndjson() {
+        let list = parse(&format!(
+            "{{\"type\":\"Feature\",\"id\":\"a\",\"geometry\":null,\"properties\":{{}},{PRODUCT}}}\n\
+             \n\
+             {{\"type\":\"Feature\",\"id\":\"b\",\"geometry\":null,\"properties\":{{}},{PRODUCT}}}\n"
+        ));
+        assert_eq!(feature_ids(&list), ["a", "b"]);
+        assert!(list.ids.is_empty());
+    }
+
+    #[test]
+    fn reads_mixed_id_lists() {
+        let list = parse("a,b c\n  d\t e,\n\nb\n");
+        assert_eq!(list.ids, ["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn skips_comments() {
+        let list = parse("# from last night's sync\na\n  # b\nc\n");
+        assert_eq!(list.ids, ["a", "c"]);
+    }
+
+    #[test]
+    fn leaves_out_ids_already_listed_as_features() {
+        let list = parse(&format!(
+            "{{\"type\":\"Feature\",\"id\":\"a\",\"geometry\":null,\"properties\":{{}},{PRODUCT}}}\na\nb\n"
+        ));
+        assert_eq!(feature_ids(&list), ["a"]);
+        assert_eq!(list.ids, ["b"]);
+    }
+
+    #[test]
+    fn refuses_geometries_and_bad_lines() {
+        let mut list = ProductList::default();
+        assert!(parse_product_list(r#"{"type":"Point","coordinates":[1,2]}"#, &mut list).is_err());
+        let error = parse_product_list("a\n{\"type\":\"Feature\"\n", &mut list).unwrap_err();
+        assert!(error.to_string().starts_with("line 2:"));
+    }
+}
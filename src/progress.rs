@@ -0,0 +1,476 @@
+use std::io::{stderr, IsTerminal, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde_json::{json, Value};
+use spinners::{Spinner, Spinners};
+use terminal_size::{terminal_size, Width};
+
+use crate::args::ProgressStyle;
+use crate::util::format_size;
+
+/*
+ * Progress reporting for long running work: searches and other requests, and
+ * downloads, one at a time, possibly as part of a batch. How it's shown is up to
+ * the reporter picked with --progress, and all of them write to stderr, so
+ * progress never mixes with results on stdout.
+ */
+pub trait Reporter: Send {
+    // Something without a measure of progress, e.g. a search
+    fn task_started(&mut self, message: &str);
+    fn task_finished(&mut self);
+    // A number of downloads about to run, with their total size where known
+    fn batch_started(&mut self, files: usize, total_bytes: Option<u64>);
+    fn download_started(&mut self, id: &str, total_bytes: Option<u64>, resumed_from: u64);
+    // Bytes of the current download so far, including any resumed from
+    fn download_progress(&mut self, bytes: u64);
+    fn download_finished(&mut self, destination: &Path, bytes: u64);
+    fn download_failed(&mut self, id: &str, error: &str);
+    // Anything else worth telling, e.g. waiting for the download window
+    fn message(&mut self, text: &str);
+}
+
+// Reporter used by everything, set once from the command line
+static REPORTER: Mutex<Option<Box<dyn Reporter>>> = Mutex::new(None);
+
+/*
+ * Picks the reporter. auto shows progress bars on a terminal, and plain lines
+ * otherwise, e.g. in logs, which don't want a line per redraw.
+ */
+pub fn set_progress_style(style: &ProgressStyle, quiet: bool) {
+    let reporter: Box<dyn Reporter> = match (style, quiet) {
+        (_, true) | (ProgressStyle::None, _) => Box::new(QuietReporter),
+        (ProgressStyle::Bar, _) => Box::new(BarReporter::default()),
+        (ProgressStyle::Plain, _) => Box::new(PlainReporter::default()),
+        (ProgressStyle::Json, _) => Box::new(JsonReporter::default()),
+        (ProgressStyle::Auto, _) if stderr().is_terminal() => Box::new(BarReporter::default()),
+        (ProgressStyle::Auto, _) => Box::new(PlainReporter::default()),
+    };
+    if let Ok(mut current) = REPORTER.lock() {
+        *current = Some(reporter);
+    }
+}
+
+fn report(event: impl FnOnce(&mut dyn Reporter)) {
+    if let Ok(mut current) = REPORTER.lock() {
+        if let Some(reporter) = current.as_mut() {
+            event(reporter.as_mut());
+        }
+    }
+}
+
+/*
+ * Starts a task, which finishes when the returned value is dropped, so one
+ * that ends early with an error doesn't leave a spinner going.
+ */
+#[must_use]
+pub fn task(message: &str) -> Task {
+    report(|r| r.task_started(message));
+    Task
+}
+
+pub struct Task;
+
+impl Task {
+    pub fn finish(self) {}
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        report(|r| r.task_finished());
+    }
+}
+
+pub fn batch_started(files: usize, total_bytes: Option<u64>) {
+    report(|r| r.batch_started(files, total_bytes));
+}
+
+pub fn download_started(id: &str, total_bytes: Option<u64>, resumed_from: u64) {
+    report(|r| r.download_started(id, total_bytes, resumed_from));
+}
+
+pub fn download_progress(bytes: u64) {
+    report(|r| r.download_progress(bytes));
+}
+
+pub fn download_finished(destination: &Path, bytes: u64) {
+    report(|r| r.download_finished(destination, bytes));
+}
+
+pub fn download_failed(id: &str, error: &str) {
+    report(|r| r.download_failed(id, error));
+}
+
+pub fn message(text: &str) {
+    report(|r| r.message(text));
+}
+
+// Shared bookkeeping for reporters that show rates and estimates
+
+#[derive(Debug)]
+struct Transfer {
+    id: String,
+    total_bytes: Option<u64>,
+    resumed_from: u64,
+    bytes: u64,
+    started: Instant,
+}
+
+impl Transfer {
+    fn new(id: &str, total_bytes: Option<u64>, resumed_from: u64) -> Self {
+        Transfer { id: id.to_string(), total_bytes, resumed_from, bytes: resumed_from, started: Instant::now() }
+    }
+
+    // Bytes per second this run, leaving out what was resumed
+    fn rate(&self) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed > 0.0 { (self.bytes - self.resumed_from) as f64 / elapsed } else { 0.0 }
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        eta(self.total_bytes?.saturating_sub(self.bytes), self.rate())
+    }
+
+    fn fraction(&self) -> Option<f64> {
+        self.total_bytes.filter(|&t| t > 0).map(|t| (self.bytes as f64 / t as f64).min(1.0))
+    }
+}
+
+#[derive(Debug)]
+struct Batch {
+    files: usize,
+    total_bytes: Option<u64>,
+    done_files: usize,
+    done_bytes: u64,
+    started: Instant,
+}
+
+fn eta(remaining: u64, rate: f64) -> Option<Duration> {
+    if rate > 0.0 { Some(Duration::from_secs_f64(remaining as f64 / rate)) } else { None }
+}
+
+// Durations in human terms, e.g. 1h02m, 3m05s or 12s
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s:02}s"),
+        (h, m, _) => format!("{h}h{m:02}m"),
+    }
+}
+
+fn format_rate(rate: f64) -> String {
+    format!("{}/s", format_size(rate as u64))
+}
+
+/*
+ * Progress bars on a terminal: a line for the current download with its rate and
+ * time left, and one for the whole batch below it, redrawn in place. Finished
+ * downloads leave a line behind.
+ */
+#[derive(Default)]
+struct BarReporter {
+    spinner: Option<Spinner>,
+    batch: Option<Batch>,
+    current: Option<Transfer>,
+    // Lines drawn last time, to clear them before the next
+    drawn: usize,
+    last_draw: Option<Instant>,
+}
+
+// Redrawing more often than this only costs time
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+impl BarReporter {
+    fn clear(&mut self) {
+        if self.drawn == 0 {
+            return;
+        }
+        let mut out = String::from("\r\x1b[2K");
+        for _ in 1..self.drawn {
+            out.push_str("\x1b[1A\x1b[2K");
+        }
+        eprint!("{out}");
+        self.drawn = 0;
+    }
+
+    fn draw(&mut self) {
+        let width = terminal_size().map(|(Width(w), _)| w as usize).unwrap_or(80).max(20);
+        let mut lines = Vec::new();
+        if let Some(current) = &self.current {
+            let detail = match current.total_bytes {
+                Some(total) => format!("{}/{}", format_size(current.bytes), format_size(total)),
+                None => format_size(current.bytes),
+            };
+            let eta = current.eta().map(|e| format!(" ETA {}", format_duration(e))).unwrap_or_default();
+            lines.push(bar_line(&current.id, current.fraction(), &format!("{detail} {}{eta}", format_rate(current.rate())), width));
+        }
+        if let Some(batch) = self.batch.as_ref().filter(|b| b.files > 1) {
+            let bytes = batch.done_bytes + self.current.as_ref().map(|c| c.bytes - c.resumed_from).unwrap_or(0);
+            let elapsed = batch.started.elapsed().as_secs_f64();
+            let rate = if elapsed > 0.0 { bytes as f64 / elapsed } else { 0.0 };
+            let fraction = match batch.total_bytes.filter(|&t| t > 0) {
+                Some(total) => Some((bytes as f64 / total as f64).min(1.0)),
+                None => Some(batch.done_files as f64 / batch.files as f64),
+            };
+            let eta = batch.total_bytes
+                .and_then(|total| eta(total.saturating_sub(bytes), rate))
+                .map(|e| format!(" ETA {}", format_duration(e)))
+                .unwrap_or_default();
+            let detail = format!("{}/{} files {}{eta}", batch.done_files, batch.files, format_size(bytes));
+            lines.push(bar_line("Overall", fraction, &detail, width));
+        }
+        self.clear();
+        eprint!("{}", lines.join("\n"));
+        let _ = stderr().flush();
+        self.drawn = lines.len();
+        self.last_draw = Some(Instant::now());
+    }
+
+    // Prints a line that stays, above the bars
+    fn persist(&mut self, line: &str) {
+        self.clear();
+        eprintln!("{line}");
+    }
+}
+
+/*
+ * A label, a bar and details, fit to the terminal so lines don't wrap, which
+ * would throw off redrawing.
+ */
+fn bar_line(label: &str, fraction: Option<f64>, detail: &str, width: usize) -> String {
+    let label: String = if label.chars().count() > 32 {
+        let mut short: String = label.chars().take(31).collect();
+        short.push('…');
+        short
+    } else {
+        label.to_string()
+    };
+    let bar_width = width.saturating_sub(label.chars().count() + detail.chars().count() + 12).clamp(0, 40);
+    let bar = match fraction {
+        Some(fraction) if bar_width > 0 => {
+            let filled = (fraction * bar_width as f64).round() as usize;
+            format!(" [{}{}] {:5.1}%", "#".repeat(filled), "-".repeat(bar_width - filled), fraction * 100.0)
+        },
+        Some(fraction) => format!(" {:5.1}%", fraction * 100.0),
+        None => String::new(),
+    };
+    let line = format!("{label}{bar} {detail}");
+    line.chars().take(width - 1).collect()
+}
+
+impl Reporter for BarReporter {
+    fn task_started(&mut self, message: &str) {
+        self.clear();
+        self.spinner = Some(Spinner::new(Spinners::Dots, message.to_string()));
+    }
+
+    fn task_finished(&mut self) {
+        if let Some(mut spinner) = self.spinner.take() {
+            spinner.stop_with_newline();
+        }
+    }
+
+    fn batch_started(&mut self, files: usize, total_bytes: Option<u64>) {
+        self.batch = Some(Batch { files, total_bytes, done_files: 0, done_bytes: 0, started: Instant::now() });
+    }
+
+    fn download_started(&mut self, id: &str, total_bytes: Option<u64>, resumed_from: u64) {
+        if resumed_from > 0 {
+            self.persist(&format!("Resuming {id} from {}", format_size(resumed_from)));
+        }
+        self.current = Some(Transfer::new(id, total_bytes, resumed_from));
+        self.draw();
+    }
+
+    fn download_progress(&mut self, bytes: u64) {
+        if let Some(current) = self.current.as_mut() {
+            current.bytes = bytes;
+        }
+        if self.last_draw.is_none_or(|last| last.elapsed() >= REDRAW_INTERVAL) {
+            self.draw();
+        }
+    }
+
+    fn download_finished(&mut self, destination: &Path, bytes: u64) {
+        if let Some(current) = self.current.take() {
+            if let Some(batch) = self.batch.as_mut() {
+                batch.done_files += 1;
+                batch.done_bytes += bytes - current.resumed_from;
+            }
+            self.persist(&format!(
+                "Downloaded {} to {}: {} in {} ({})",
+                current.id, destination.display(), format_size(bytes), format_duration(current.started.elapsed()), format_rate(current.rate())
+            ));
+        }
+    }
+
+    fn download_failed(&mut self, id: &str, error: &str) {
+        self.current = None;
+        if let Some(batch) = self.batch.as_mut() {
+            batch.done_files += 1;
+        }
+        self.persist(&format!("Failed to download {id}: {error}"));
+    }
+
+    fn message(&mut self, text: &str) {
+        self.persist(text);
+    }
+}
+
+/*
+ * A line when each download starts and ends, and nothing in between, for logs
+ * and anything else that isn't a terminal.
+ */
+#[derive(Default)]
+struct PlainReporter {
+    current: Option<Transfer>,
+}
+
+impl Reporter for PlainReporter {
+    fn task_started(&mut self, message: &str) {
+        eprintln!("{message}");
+    }
+
+    fn task_finished(&mut self) {}
+
+    fn batch_started(&mut self, files: usize, total_bytes: Option<u64>) {
+        match total_bytes {
+            Some(total) => eprintln!("Downloading {files} product(s), {}", format_size(total)),
+            None => eprintln!("Downloading {files} product(s)"),
+        }
+    }
+
+    fn download_started(&mut self, id: &str, total_bytes: Option<u64>, resumed_from: u64) {
+        let size = total_bytes.map(|t| format!(" ({})", format_size(t))).unwrap_or_default();
+        if resumed_from > 0 {
+            eprintln!("Resuming {id}{size} from {}", format_size(resumed_from));
+        } else {
+            eprintln!("Downloading {id}{size}");
+        }
+        self.current = Some(Transfer::new(id, total_bytes, resumed_from));
+    }
+
+    fn download_progress(&mut self, bytes: u64) {
+        if let Some(current) = self.current.as_mut() {
+            current.bytes = bytes;
+        }
+    }
+
+    fn download_finished(&mut self, destination: &Path, bytes: u64) {
+        if let Some(current) = self.current.take() {
+            eprintln!(
+                "Downloaded {} to {}: {} in {} ({})",
+                current.id, destination.display(), format_size(bytes), format_duration(current.started.elapsed()), format_rate(current.rate())
+            );
+        }
+    }
+
+    fn download_failed(&mut self, id: &str, error: &str) {
+        self.current = None;
+        eprintln!("Failed to download {id}: {error}");
+    }
+
+    fn message(&mut self, text: &str) {
+        eprintln!("{text}");
+    }
+}
+
+/*
+ * Newline delimited JSON, one event per line, for other programs to follow.
+ * Every event has "event" and "time", and download progress comes at most once
+ * a second per download.
+ */
+#[derive(Default)]
+struct JsonReporter {
+    current: Option<Transfer>,
+    last_progress: Option<Instant>,
+}
+
+const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+impl JsonReporter {
+    fn emit(&self, event: &str, mut fields: Value) {
+        fields["event"] = json!(event);
+        fields["time"] = json!(Utc::now().to_rfc3339());
+        eprintln!("{fields}");
+    }
+
+    fn emit_progress(&mut self) {
+        if let Some(current) = &self.current {
+            self.emit("download_progress", json!({
+                "id": current.id,
+                "bytes": current.bytes,
+                "total_bytes": current.total_bytes,
+                "bytes_per_second": current.rate().round() as u64,
+                "eta_seconds": current.eta().map(|e| e.as_secs_f64().round() as u64),
+            }));
+        }
+        self.last_progress = Some(Instant::now());
+    }
+}
+
+impl Reporter for JsonReporter {
+    fn task_started(&mut self, message: &str) {
+        self.emit("task_started", json!({ "message": message }));
+    }
+
+    fn task_finished(&mut self) {
+        self.emit("task_finished", json!({}));
+    }
+
+    fn batch_started(&mut self, files: usize, total_bytes: Option<u64>) {
+        self.emit("batch_started", json!({ "files": files, "total_bytes": total_bytes }));
+    }
+
+    fn download_started(&mut self, id: &str, total_bytes: Option<u64>, resumed_from: u64) {
+        self.emit("download_started", json!({ "id": id, "total_bytes": total_bytes, "resumed_from": resumed_from }));
+        self.current = Some(Transfer::new(id, total_bytes, resumed_from));
+        self.last_progress = Some(Instant::now());
+    }
+
+    fn download_progress(&mut self, bytes: u64) {
+        if let Some(current) = self.current.as_mut() {
+            current.bytes = bytes;
+        }
+        if self.last_progress.is_none_or(|last| last.elapsed() >= PROGRESS_EVENT_INTERVAL) {
+            self.emit_progress();
+        }
+    }
+
+    fn download_finished(&mut self, destination: &Path, bytes: u64) {
+        if let Some(current) = self.current.take() {
+            self.emit("download_finished", json!({
+                "id": current.id,
+                "path": destination.display().to_string(),
+                "bytes": bytes,
+                "seconds": current.started.elapsed().as_secs_f64(),
+            }));
+        }
+    }
+
+    fn download_failed(&mut self, id: &str, error: &str) {
+        self.current = None;
+        self.emit("download_failed", json!({ "id": id, "error": error }));
+    }
+
+    fn message(&mut self, text: &str) {
+        self.emit("message", json!({ "message": text }));
+    }
+}
+
+// --quiet, or --progress none
+struct QuietReporter;
+
+impl Reporter for QuietReporter {
+    fn task_started(&mut self, _message: &str) {}
+    fn task_finished(&mut self) {}
+    fn batch_started(&mut self, _files: usize, _total_bytes: Option<u64>) {}
+    fn download_started(&mut self, _id: &str, _total_bytes: Option<u64>, _resumed_from: u64) {}
+    fn download_progress(&mut self, _bytes: u64) {}
+    fn download_finished(&mut self, _destination: &Path, _bytes: u64) {}
+    fn download_failed(&mut self, _id: &str, _error: &str) {}
+    fn message(&mut self, _text: &str) {}
+}
@@ -0,0 +1,182 @@
+use std::error::Error;
+use std::fs;
+use std::path::{self, PathBuf};
+
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+
+use crate::util::{config_dir, write_private};
+
+// Kept next to the config, like tokens
+const QUEUE_FILE: &str = "queue.json";
+
+/*
+ * Downloads waiting to run, or that have run, saved after every change so an
+ * interrupted batch can pick up where it stopped. Items still in progress when
+ * loaded were interrupted, and go back to pending; their partial downloads are
+ * resumed.
+ */
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Queue {
+    pub items: Vec<QueueItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueueItem {
+    pub id: String,
+    pub output_dir: Option<String>,
+    #[serde(default)]
+    pub name_template: Option<String>,
+    pub state: ItemState,
+    #[serde(default)]
+    pub attempts: u32,
+    // Why the last attempt failed
+    #[serde(default)]
+    pub error: Option<String>,
+    // Unix timestamps, like acquired_time for tokens
+    pub added: i64,
+    #[serde(default)]
+    pub updated: i64,
+    #[serde(default)]
+    pub destination: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ItemState {
+    Pending,
+    InProgress,
+    Done,
+    Failed,
+}
+
+impl ItemState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ItemState::Pending => "pending",
+            ItemState::InProgress => "in-progress",
+            ItemState::Done => "done",
+            ItemState::Failed => "failed",
+        }
+    }
+}
+
+impl Queue {
+    pub fn path() -> Result<PathBuf, Box<dyn Error>> {
+        Ok(config_dir()?.join(QUEUE_FILE))
+    }
+
+    pub fn load() -> Result<Queue, Box<dyn Error>> {
+        let path = Queue::path()?;
+        if !path.exists() {
+            return Ok(Queue::default());
+        }
+        let contents = fs::read(&path)?;
+        serde_json::from_slice(&contents).map_err(|e| format!("Unable to read queue {}: {e}", path.display()).into())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        write_private(&Queue::path()?, &serde_json::to_vec_pretty(self)?)
+    }
+
+    /*
+     * Queues ids for download into output_dir, named by name_template if there is
+     * one. Ids already waiting are left as
+     * they are, and finished or failed ones are queued again. Returns how many
+     * were queued. The directory is kept as an absolute path, so runs from
+     * elsewhere still download to it.
+     */
+    pub fn add(&mut self, ids: &[String], output_dir: &Option<String>, name_template: &Option<String>) -> Result<usize, Box<dyn Error>> {
+        let output_dir = Some(path::absolute(output_dir.as_deref().unwrap_or("./"))?.to_string_lossy().into_owned());
+        let mut added = 0;
+        for id in ids {
+            match self.items.iter_mut().find(|i| &i.id == id) {
+                Some(item) if matches!(item.state, ItemState::Pending | ItemState::InProgress) => (),
+                Some(item) => {
+                    item.output_dir = output_dir.clone();
+                    item.name_template = name_template.clone();
+                    item.error = None;
+                    self.set_state_of(id, ItemState::Pending);
+                    added += 1;
+                },
+                None => {
+                    self.items.push(QueueItem {
+                        id: id.clone(),
+                        output_dir: output_dir.clone(),
+                        name_template: name_template.clone(),
+                        state: ItemState::Pending,
+                        attempts: 0,
+                        error: None,
+                        added: Utc::now().timestamp(),
+                        updated: Utc::now().timestamp(),
+                        destination: None,
+                    });
+                    added += 1;
+                },
+            }
+        }
+        Ok(added)
+    }
+
+    // Puts interrupted items back to pending. Returns how many there were.
+    pub fn recover(&mut self) -> usize {
+        self.requeue(ItemState::InProgress)
+    }
+
+    pub fn retry_failed(&mut self) -> usize {
+        self.requeue(ItemState::Failed)
+    }
+
+    fn requeue(&mut self, state: ItemState) -> usize {
+        let ids: Vec<String> = self.items.iter().filter(|i| i.state == state).map(|i| i.id.clone()).collect();
+        for id in &ids {
+            self.set_state_of(id, ItemState::Pending);
+        }
+        ids.len()
+    }
+
+    pub fn pending(&self) -> Vec<QueueItem> {
+        self.items.iter().filter(|i| i.state == ItemState::Pending).cloned().collect()
+    }
+
+    pub fn count(&self, state: ItemState) -> usize {
+        self.items.iter().filter(|i| i.state == state).count()
+    }
+
+    // Marks an item as being downloaded, counting the attempt
+    pub fn start(&mut self, id: &str) {
+        if let Some(item) = self.items.iter_mut().find(|i| i.id == id) {
+            item.attempts += 1;
+        }
+        self.set_state_of(id, ItemState::InProgress);
+    }
+
+    pub fn finish(&mut self, id: &str, destination: PathBuf) {
+        if let Some(item) = self.items.iter_mut().find(|i| i.id == id) {
+            item.destination = Some(destination);
+            item.error = None;
+        }
+        self.set_state_of(id, ItemState::Done);
+    }
+
+    pub fn fail(&mut self, id: &str, error: String) {
+        if let Some(item) = self.items.iter_mut().find(|i| i.id == id) {
+            item.error = Some(error);
+        }
+        self.set_state_of(id, ItemState::Failed);
+    }
+
+    fn set_state_of(&mut self, id: &str, state: ItemState) {
+        if let Some(item) = self.items.iter_mut().find(|i| i.id == id) {
+            item.state = state;
+            item.updated = Utc::now().timestamp();
+        }
+    }
+
+    // Removes finished items, or everything. Returns how many were removed.
+    pub fn clear(&mut self, all: bool) -> usize {
+        let before = self.items.len();
+        self.items.retain(|i| !all && i.state != ItemState::Done);
+        before - self.items.len()
+    }
+}
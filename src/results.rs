@@ -0,0 +1,50 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use geojson::{Feature, FeatureCollection, GeoJson};
+
+use crate::util::{config_dir, write_private};
+
+// Kept next to the config, like the queue
+const LAST_RESULTS_FILE: &str = "last_search.geojson";
+
+/*
+ * Results of the last search, in the order they were listed, so they can be
+ * picked by the number shown next to them, e.g. download 1 4 7. Features are
+ * kept whole, so downloading them doesn't look them up again.
+ */
+pub fn last_results_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(config_dir()?.join(LAST_RESULTS_FILE))
+}
+
+pub fn save_last_results(fc: &FeatureCollection) -> Result<(), Box<dyn Error>> {
+    write_private(&last_results_path()?, fc.to_string().as_bytes())
+}
+
+pub fn load_last_results() -> Result<FeatureCollection, Box<dyn Error>> {
+    let path = last_results_path()?;
+    if !path.exists() {
+        return Err("No earlier search to pick results from. Run a search first.".into());
+    }
+    let contents = fs::read_to_string(&path)?;
+    let geojson = contents.parse::<GeoJson>().map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+    Ok(FeatureCollection::try_from(geojson)?)
+}
+
+/*
+ * Picks results by their listed number, counting from 1, in the order asked
+ * for, once each. Numbers past the end are an error rather than skipped, since
+ * they likely mean the results aren't the ones expected.
+ */
+pub fn pick_results(fc: &FeatureCollection, numbers: &[usize]) -> Result<Vec<Feature>, Box<dyn Error>> {
+    let mut unique = numbers.to_vec();
+    let mut seen = Vec::new();
+    unique.retain(|n| if seen.contains(n) { false } else { seen.push(*n); true });
+    unique.iter().map(|&n| {
+        n.checked_sub(1)
+            .and_then(|i| fc.features.get(i))
+            .cloned()
+            .ok_or(format!("No result {n} in the last search, which had {}", fc.features.len()).into())
+    }).collect()
+}
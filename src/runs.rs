@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use geojson::FeatureCollection;
+use serde::{Serialize, Deserialize};
+
+use crate::args::SearchArgs;
+use crate::util::{config_dir, write_private};
+
+// Kept next to the config, like the queue
+const RUNS_FILE: &str = "runs.json";
+
+/*
+ * When searches with --published-since last ran, so the next run can ask for
+ * only what's been published since. Searches are told apart by what they look
+ * for, so separate syncs, e.g. of different tiles, keep separate times.
+ */
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RunLog {
+    // Unix timestamps by search, like acquired_time for tokens
+    pub searches: HashMap<String, i64>,
+}
+
+impl RunLog {
+    pub fn path() -> Result<PathBuf, Box<dyn Error>> {
+        Ok(config_dir()?.join(RUNS_FILE))
+    }
+
+    pub fn load() -> Result<RunLog, Box<dyn Error>> {
+        let path = RunLog::path()?;
+        if !path.exists() {
+            return Ok(RunLog::default());
+        }
+        let contents = fs::read(&path)?;
+        serde_json::from_slice(&contents).map_err(|e| format!("Unable to read run log {}: {e}", path.display()).into())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        write_private(&RunLog::path()?, &serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn last_run(&self, search_args: &SearchArgs) -> Option<DateTime<Utc>> {
+        self.searches.get(&search_key(search_args)).and_then(|&ts| DateTime::from_timestamp(ts, 0))
+    }
+
+    pub fn record(&mut self, search_args: &SearchArgs, started: DateTime<Utc>) {
+        self.searches.insert(search_key(search_args), started.timestamp());
+    }
+}
+
+// When the most recently published of the results was, from OData's PublicationDate
+pub fn latest_publication(fc: &FeatureCollection) -> Option<DateTime<Utc>> {
+    fc.features.iter()
+        .filter_map(|f| f.properties.as_ref()?.get("publicationDate")?.as_str().map(String::from))
+        .filter_map(|d| DateTime::parse_from_rfc3339(&d).ok().map(|d| d.with_timezone(&Utc)))
+        .max()
+}
+
+/*
+ * What a search looks for, leaving out dates, paging and display, which change
+ * between runs of the same sync. Readable, so the run log is too.
+ */
+fn search_key(a: &SearchArgs) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    let mut push = |name: &str, value: Option<String>| {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            parts.push(format!("{name}={value}"));
+        }
+    };
+    push("ids", a.ids.clone());
+    push("collections", a.collections.clone());
+    push("bbox", a.bbox.clone());
+    push("tile", Some(a.tiles.join(",")));
+    push("relative-orbit", Some(a.relative_orbits.iter().map(|o| o.to_string()).collect::<Vec<String>>().join(",")));
+    push("aoi", a.aoi.clone());
+    push("polarisation", a.sentinel1.polarisation.as_ref().map(|p| p.channels().join("+")));
+    push("orbit-direction", a.sentinel1.orbit_direction.as_ref().map(|d| format!("{d:?}").to_lowercase()));
+    push("sensor-mode", a.sentinel1.sensor_mode.as_ref().map(|m| format!("{m:?}").to_lowercase()));
+    push("product-type", a.sentinel1.product_type.as_ref().map(|t| format!("{t:?}").to_lowercase()));
+    push("name", a.name.clone());
+    push("online", Some(a.online).filter(|&o| o).map(|o| o.to_string()));
+    push("attribute", Some(a.attributes.iter().map(|(k, v)| format!("{k}={v}")).collect::<Vec<String>>().join(",")));
+    if parts.is_empty() { String::from("everything") } else { parts.join(" ") }
+}
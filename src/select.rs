@@ -0,0 +1,134 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use geojson::{Feature, FeatureCollection};
+use serde_json::Value;
+
+use crate::args::Period;
+use crate::util::get_id;
+
+/*
+ * Narrows search results down to one feature per tile per period, e.g. the best
+ * scene for each tile each month. Reprocessed copies of an acquisition are
+ * dropped first, so only the latest processing baseline competes.
+ */
+pub fn select_best(fc: &FeatureCollection, period: &Period) -> FeatureCollection {
+    let latest = latest_baselines(&fc.features);
+
+    let mut windows: HashMap<(String, String), &Feature> = HashMap::new();
+    for feature in latest {
+        let window = if let Some(window) = period_key(feature, period) { window } else { continue };
+        let key = (tile_key(feature), window);
+        match windows.get(&key) {
+            Some(best) if rank(best, feature) != Ordering::Greater => (),
+            _ => { windows.insert(key, feature); },
+        }
+    }
+
+    let mut selected: Vec<(&(String, String), &Feature)> = windows.iter().map(|(k, f)| (k, *f)).collect();
+    selected.sort_by(|a, b| a.0.cmp(b.0));
+    FeatureCollection {
+        bbox: fc.bbox.clone(),
+        features: selected.into_iter().map(|(_, f)| f.clone()).collect(),
+        foreign_members: None,
+    }
+}
+
+/*
+ * Keeps the newest baseline of each acquisition and processing level. The
+ * productGroupId ends with the baseline (e.g. _N05.00), so stripping it leaves
+ * something that identifies the acquisition.
+ */
+fn latest_baselines(features: &[Feature]) -> Vec<&Feature> {
+    let mut latest: HashMap<(String, String), &Feature> = HashMap::new();
+    let mut ungrouped: Vec<&Feature> = Vec::new();
+    for feature in features {
+        let group = if let Some(group) = property_str(feature, "productGroupId") { group } else {
+            ungrouped.push(feature);
+            continue;
+        };
+        let acquisition = match group.rsplit_once("_N") {
+            Some((acquisition, _)) => acquisition.to_string(),
+            None => group,
+        };
+        let level = property_str(feature, "processingLevel").unwrap_or_default();
+        let key = (acquisition, level);
+        match latest.get(&key) {
+            Some(existing) if newer_baseline(existing, feature) != Ordering::Less => (),
+            _ => { latest.insert(key, feature); },
+        }
+    }
+    latest.into_values().chain(ungrouped).collect()
+}
+
+fn newer_baseline(a: &Feature, b: &Feature) -> Ordering {
+    baseline(a).cmp(&baseline(b)).then_with(|| processing_time(a).cmp(&processing_time(b)))
+}
+
+/*
+ * Orders features best first: lowest cloud cover, then the most recent
+ * processing. Features without cloud cover sort last.
+ */
+fn rank(a: &Feature, b: &Feature) -> Ordering {
+    let cloud = |f: &Feature| property(f, "cloudCover").and_then(|v| v.as_f64()).unwrap_or(f64::MAX);
+    cloud(a).partial_cmp(&cloud(b)).unwrap_or(Ordering::Equal)
+        .then_with(|| processing_time(b).cmp(&processing_time(a)))
+}
+
+// Sentinel-2 products are organized by tile. Others end up in a single group.
+fn tile_key(feature: &Feature) -> String {
+    property_str(feature, "tileId").unwrap_or_default()
+}
+
+fn period_key(feature: &Feature, period: &Period) -> Option<String> {
+    let datetime = property_str(feature, "datetime")?;
+    let datetime: DateTime<Utc> = DateTime::parse_from_rfc3339(&datetime).ok()?.into();
+    Some(match period {
+        Period::Day => datetime.format("%F").to_string(),
+        Period::Week => {
+            let week = datetime.iso_week();
+            format!("{}-W{:02}", week.year(), week.week())
+        },
+        Period::Month => datetime.format("%Y-%m").to_string(),
+        Period::Year => datetime.format("%Y").to_string(),
+    })
+}
+
+/*
+ * Processing baseline as a number, e.g. 500 for 05.00, from processorVersion or
+ * the N0500 part of the product id.
+ */
+fn baseline(feature: &Feature) -> u32 {
+    if let Some(version) = property_str(feature, "processorVersion") {
+        if let Ok(v) = version.replace('.', "").parse::<u32>() {
+            return v;
+        }
+    }
+    get_id(&feature.id).unwrap_or_default().split('_')
+        .find_map(|part| part.strip_prefix('N').and_then(|n| n.parse::<u32>().ok()))
+        .unwrap_or_default()
+}
+
+/*
+ * When the product was processed. Older baselines don't carry processingDate,
+ * but the product id ends with the same timestamp, e.g. _20211002T193901.SAFE.
+ */
+fn processing_time(feature: &Feature) -> Option<DateTime<Utc>> {
+    if let Some(date) = property_str(feature, "processingDate") {
+        if let Ok(dt) = DateTime::parse_from_rfc3339(&date) {
+            return Some(dt.into());
+        }
+    }
+    let id = get_id(&feature.id)?;
+    let stamp = id.trim_end_matches(".SAFE").rsplit('_').next()?;
+    NaiveDateTime::parse_from_str(stamp, "%Y%m%dT%H%M%S").ok().map(|dt| dt.and_utc())
+}
+
+fn property<'a>(feature: &'a Feature, name: &str) -> Option<&'a Value> {
+    feature.properties.as_ref()?.get(name)
+}
+
+fn property_str(feature: &Feature, name: &str) -> Option<String> {
+    property(feature, name)?.as_str().map(String::from)
+}
@@ -0,0 +1,170 @@
+use std::error::Error;
+
+use clap::{Args, ValueEnum};
+use geojson::Feature;
+
+use crate::util::get_value;
+
+/*
+ * Sentinel-1 search options. These are radar acquisition settings, mapped to the
+ * catalogue's properties for them: polarisationChannels, orbitDirection,
+ * operationalMode and productType. OData searches filter by them, and STAC
+ * results are checked against them, like tiles and orbits.
+ */
+#[derive(Clone, Debug, Default, Args)]
+pub struct Sentinel1Args {
+    #[arg(
+        long,
+        value_parser = parse_polarisation,
+        help = "Sentinel-1 polarisation channels: VV, HH, VV+VH or HH+HV"
+    )]
+    pub polarisation: Option<Polarisation>,
+    #[arg(long = "orbit-direction", value_enum, help = "Sentinel-1 orbit direction")]
+    pub orbit_direction: Option<OrbitDirection>,
+    #[arg(long = "sensor-mode", value_enum, help = "Sentinel-1 acquisition mode, e.g. iw for Interferometric Wide swath")]
+    pub sensor_mode: Option<SensorMode>,
+    #[arg(long = "product-type", value_enum, help = "Sentinel-1 product type")]
+    pub product_type: Option<ProductType>,
+}
+
+impl Sentinel1Args {
+    pub fn is_empty(&self) -> bool {
+        self.polarisation.is_none() && self.orbit_direction.is_none() && self.sensor_mode.is_none() && self.product_type.is_none()
+    }
+
+    // Option names in use, for errors about them
+    pub fn in_use(&self) -> Vec<&'static str> {
+        [
+            ("--polarisation", self.polarisation.is_some()),
+            ("--orbit-direction", self.orbit_direction.is_some()),
+            ("--sensor-mode", self.sensor_mode.is_some()),
+            ("--product-type", self.product_type.is_some()),
+        ].iter().filter(|(_, used)| *used).map(|(name, _)| *name).collect()
+    }
+
+    /*
+     * Checks a feature against the options given. Empty options match
+     * everything, and features missing a property don't match an option for it.
+     */
+    pub fn matches(&self, feature: &Feature) -> bool {
+        let property = |name: &str| feature.properties.as_ref()
+            .and_then(|p| get_value(p.get(name).cloned()))
+            .filter(|v| !v.is_empty() && v != "N/A");
+        let polarisation_ok = self.polarisation.as_ref().is_none_or(|wanted| property("polarisationChannels")
+            .is_some_and(|channels| &channel_set(&channels) == wanted.channels()));
+        let direction_ok = self.orbit_direction.as_ref().is_none_or(|wanted| property("orbitDirection")
+            .is_some_and(|direction| direction.eq_ignore_ascii_case(wanted.catalogue_value())));
+        let mode_ok = self.sensor_mode.as_ref().is_none_or(|wanted| property("operationalMode")
+            .is_some_and(|mode| mode.eq_ignore_ascii_case(wanted.catalogue_value())));
+        let type_ok = self.product_type.as_ref().is_none_or(|wanted| property("productType")
+            .is_some_and(|product_type| wanted.matches(&product_type)));
+        polarisation_ok && direction_ok && mode_ok && type_ok
+    }
+}
+
+/*
+ * Channels a product was acquired with. Sentinel-1 transmits in one
+ * polarisation and receives in one or both, so only these four are possible.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polarisation(Vec<String>);
+
+impl Polarisation {
+    pub fn channels(&self) -> &Vec<String> {
+        &self.0
+    }
+
+    // As the catalogue writes it, e.g. VV&VH, co-polarised channel first
+    pub fn catalogue_value(&self) -> String {
+        POLARISATIONS.iter().find(|p| &channel_set(p) == self.channels()).copied().unwrap_or_default().replace('+', "&")
+    }
+}
+
+const POLARISATIONS: [&str; 4] = ["VV", "HH", "VV+VH", "HH+HV"];
+
+pub fn parse_polarisation(s: &str) -> Result<Polarisation, Box<dyn Error + Send + Sync>> {
+    let channels = channel_set(s);
+    let valid = POLARISATIONS.iter().any(|p| channel_set(p) == channels);
+    if valid {
+        Ok(Polarisation(channels))
+    } else {
+        Err(format!("Not a Sentinel-1 polarisation: {s}, expected one of {}", POLARISATIONS.join(", ")).into())
+    }
+}
+
+// Channels in a canonical order, however they're joined, e.g. VV&VH, VH+VV or "VV, VH"
+fn channel_set(s: &str) -> Vec<String> {
+    let mut channels: Vec<String> = s.split(['+', '&', ',', ' ', '/'])
+        .filter(|c| !c.is_empty())
+        .map(|c| c.to_uppercase())
+        .collect();
+    channels.sort();
+    channels.dedup();
+    channels
+}
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum OrbitDirection {
+    Ascending,
+    Descending,
+}
+
+impl OrbitDirection {
+    pub fn catalogue_value(&self) -> &'static str {
+        match self {
+            OrbitDirection::Ascending => "ASCENDING",
+            OrbitDirection::Descending => "DESCENDING",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum SensorMode {
+    // Stripmap
+    Sm,
+    // Interferometric Wide swath
+    Iw,
+    // Extra Wide swath
+    Ew,
+    // Wave
+    Wv,
+}
+
+impl SensorMode {
+    pub fn catalogue_value(&self) -> &'static str {
+        match self {
+            SensorMode::Sm => "SM",
+            SensorMode::Iw => "IW",
+            SensorMode::Ew => "EW",
+            SensorMode::Wv => "WV",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum ProductType {
+    Raw,
+    Slc,
+    Grd,
+    Ocn,
+}
+
+impl ProductType {
+    pub fn catalogue_value(&self) -> &'static str {
+        match self {
+            ProductType::Raw => "RAW",
+            ProductType::Slc => "SLC",
+            ProductType::Grd => "GRD",
+            ProductType::Ocn => "OCN",
+        }
+    }
+
+    /*
+     * The catalogue gives either the bare type, e.g. GRD, or the full one with
+     * mode and resolution, e.g. IW_GRDH_1S, where GRDH and GRDM are both GRD.
+     */
+    fn matches(&self, product_type: &str) -> bool {
+        let wanted = self.catalogue_value();
+        product_type.to_uppercase().split('_').any(|part| part.starts_with(wanted))
+    }
+}
@@ -0,0 +1,174 @@
+use std::cmp::Ordering;
+
+use colored::Colorize;
+use geojson::{Feature, FeatureCollection};
+use serde_json::Value;
+use terminal_size::{terminal_size, Width};
+
+use crate::args::Column;
+use crate::progress;
+use crate::theme::Theme;
+use crate::util::{format_size, from_path, get_id, get_value};
+
+// Space between columns
+const GAP: &str = "  ";
+// Narrowest the id column gets truncated to, so ids stay recognizable
+const MIN_ID_WIDTH: usize = 24;
+// Numbers rows, for picking results of the last search
+const INDEX_HEADER: &str = "#";
+
+/*
+ * Compact listing, one row per feature. Columns are sized to their contents, and
+ * when that doesn't fit the terminal the id column is truncated, since the rest
+ * are short already. Rows are numbered like the regular listing.
+ */
+pub fn format_table(fc: &FeatureCollection, columns: &[Column], theme: &Theme) -> String {
+    let columns = &available_columns(fc, columns);
+    let rows: Vec<Vec<String>> = fc.features.iter()
+        .map(|f| columns.iter().map(|c| column_value(f, c)).collect())
+        .collect();
+
+    let index_width = rows.len().to_string().len().max(INDEX_HEADER.len());
+    let mut widths: Vec<usize> = columns.iter().map(|c| header(c).len()).collect();
+    for row in &rows {
+        for (i, value) in row.iter().enumerate() {
+            widths[i] = widths[i].max(value.chars().count());
+        }
+    }
+    if let Some((Width(terminal_width), _)) = terminal_size() {
+        let total: usize = index_width + widths.iter().sum::<usize>() + GAP.len() * widths.len();
+        if let Some(id) = columns.iter().position(|c| *c == Column::Id) {
+            let excess = total.saturating_sub(terminal_width as usize);
+            widths[id] = widths[id].saturating_sub(excess).max(MIN_ID_WIDTH.min(widths[id]));
+        }
+    }
+
+    let mut output: Vec<String> = Vec::new();
+    let header_row: Vec<String> = std::iter::once(format!("{INDEX_HEADER:>index_width$}").bold().to_string())
+        .chain(columns.iter().zip(&widths).map(|(c, &w)| format!("{:w$}", header(c)).bold().to_string()))
+        .collect();
+    output.push(header_row.join(GAP).trim_end().to_string());
+    for (i, row) in rows.into_iter().enumerate() {
+        let index = (i + 1).to_string();
+        let cells: Vec<String> = std::iter::once(theme.style("INDEX", &index, &format!("{index:>index_width$}")))
+            .chain(columns.iter().zip(&widths).zip(row)
+                .map(|((c, &w), value)| style_cell(c, &value, format!("{:w$}", truncate(&value, w)), theme)))
+            .collect();
+        output.push(cells.join(GAP).trim_end().to_string());
+    }
+    output.join("\n")
+}
+
+pub fn header(column: &Column) -> &'static str {
+    match column {
+        Column::Id => "ID",
+        Column::Date => "DATE",
+        Column::Cloud => "CLOUD",
+        Column::Tile => "TILE",
+        Column::Level => "LEVEL",
+        Column::Size => "SIZE",
+    }
+}
+
+pub fn column_value(f: &Feature, column: &Column) -> String {
+    let property = |name: &str| f.properties.as_ref().and_then(|p| p.get(name).cloned());
+    let value = match column {
+        Column::Id => get_id(&f.id),
+        // Date and time are enough to tell scenes apart
+        Column::Date => property("datetime").and_then(|v| v.as_str().map(|d| d.chars().take(19).collect())),
+        Column::Cloud => property("cloudCover").and_then(|v| v.as_f64()).map(|c| format!("{c:.1}%")),
+        Column::Tile => get_value(property("tileId")),
+        Column::Level => get_value(property("processingLevel")),
+        Column::Size => product_size(f).map(format_size),
+    };
+    value.unwrap_or(String::from("N/A"))
+}
+
+/*
+ * Columns there's something to show for. Only OData results have sizes, so the
+ * size column is left out of STAC ones rather than showing N/A throughout.
+ */
+pub fn available_columns(fc: &FeatureCollection, columns: &[Column]) -> Vec<Column> {
+    let has_sizes = fc.features.iter().any(|f| product_size(f).is_some());
+    columns.iter().filter(|c| has_sizes || **c != Column::Size).cloned().collect()
+}
+
+// Product size in bytes, where the catalogue includes one, which only OData does
+pub fn product_size(f: &Feature) -> Option<u64> {
+    from_path(Vec::from(["assets", "PRODUCT", "file:size"]), &f.foreign_members).and_then(|v| v.as_u64())
+}
+
+fn truncate(value: &str, width: usize) -> String {
+    if value.chars().count() <= width {
+        value.to_string()
+    } else {
+        let mut truncated: String = value.chars().take(width.saturating_sub(1)).collect();
+        truncated.push('…');
+        truncated
+    }
+}
+
+/*
+ * Colors a padded cell with the same styles, and rules, as the regular listing.
+ * Rules check the unpadded value.
+ */
+fn style_cell(column: &Column, value: &str, cell: String, theme: &Theme) -> String {
+    let field = match column {
+        Column::Id => "ID",
+        Column::Date => "CAPTURE_TIME",
+        Column::Cloud => "CLOUD_COVER",
+        Column::Tile => "TILE",
+        Column::Level => "DETAIL",
+        Column::Size => "SIZE",
+    };
+    theme.style(field, value, &cell)
+}
+
+/*
+ * Sorts features by a property, client side. Column names work as shorthands for
+ * their property, and a leading - sorts descending. Numbers compare as numbers,
+ * and everything else as text, which suits ISO dates too. Features missing the
+ * property go last.
+ */
+pub fn sort_features(fc: &mut FeatureCollection, key: &str) {
+    let (descending, key) = match key.strip_prefix('-') {
+        Some(key) => (true, key),
+        None => (false, key.trim_start_matches('+')),
+    };
+    let property = match key {
+        "date" => "datetime",
+        "cloud" => "cloudCover",
+        "tile" => "tileId",
+        "level" => "processingLevel",
+        other => other,
+    };
+    if property == "size" && !fc.features.iter().any(|f| product_size(f).is_some()) {
+        progress::message("No sizes to sort by. Only OData results have them, see --backend odata.");
+        return;
+    }
+    let sort_value = |f: &Feature| -> Option<Value> {
+        match property {
+            "id" => get_id(&f.id).map(Value::String),
+            "size" => product_size(f).map(Value::from),
+            _ => f.properties.as_ref().and_then(|p| p.get(property).cloned()),
+        }
+    };
+    fc.features.sort_by(|a, b| {
+        match (sort_value(a), sort_value(b)) {
+            (Some(a), Some(b)) => {
+                let ordering = compare_values(&a, &b);
+                if descending { ordering.reverse() } else { ordering }
+            },
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    });
+}
+
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => get_value(Some(a.clone())).cmp(&get_value(Some(b.clone()))),
+    }
+}
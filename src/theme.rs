@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::env::var;
+use std::io::{stdout, IsTerminal};
+
+use colored::{Color, ColoredString, Colorize};
+use log::debug;
+use serde::{Serialize, Deserialize};
+
+use crate::args::ColorChoice;
+use crate::util::DisplayConfig;
+
+const CLOUD_COVER: &str = "CLOUD_COVER";
+
+/*
+ * Styles for each field in list and search output, keyed the same as template
+ * tags. TEMPLATE is the text around the values. Styles are a color name, or
+ * #rrggbb, plus any of Bold, Dimmed, Italic and Underline, e.g. "Bold Cyan".
+ */
+const DARK_THEME: [(&str, &str); 15] = [
+    ("TEMPLATE", "#808080"),
+    ("INDEX", "BrightBlack"),
+    ("ID", "Bold BrightWhite"),
+    ("SHORT_NAME", "Cyan"),
+    ("SERIAL", "Cyan"),
+    ("DETAIL", "BrightBlue"),
+    ("CAPTURE_TIME", "BrightWhite"),
+    ("CLOUD_COVER", "White"),
+    ("TILE", "Yellow"),
+    ("ORBIT", "Yellow"),
+    ("SIZE", "White"),
+    ("COVERAGE", "BrightWhite"),
+    ("BBOX", "BrightBlack"),
+    ("QUICKLOOK_HREF", "BrightBlack"),
+    ("PRODUCT_HREF", "BrightBlack"),
+];
+
+// For light backgrounds, where bright and white colors are hard to read
+const LIGHT_THEME: [(&str, &str); 15] = [
+    ("TEMPLATE", "#707070"),
+    ("INDEX", "#707070"),
+    ("ID", "Bold Black"),
+    ("SHORT_NAME", "Blue"),
+    ("SERIAL", "Blue"),
+    ("DETAIL", "Magenta"),
+    ("CAPTURE_TIME", "Black"),
+    ("CLOUD_COVER", "Black"),
+    ("TILE", "#8a6d00"),
+    ("ORBIT", "#8a6d00"),
+    ("SIZE", "Black"),
+    ("COVERAGE", "Black"),
+    ("BBOX", "#707070"),
+    ("QUICKLOOK_HREF", "#707070"),
+    ("PRODUCT_HREF", "#707070"),
+];
+
+/*
+ * Styles a field differently depending on its value, e.g. highlighting clear
+ * scenes. Numeric bounds compare values as numbers, ignoring a trailing %.
+ *
+ *   [[display.rules]]
+ *   field = "CLOUD_COVER"
+ *   below = 10
+ *   style = "Bold Green"
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StyleRule {
+    pub field: String,
+    pub below: Option<f64>,
+    pub above: Option<f64>,
+    pub equals: Option<String>,
+    pub style: String,
+}
+
+impl StyleRule {
+    fn matches(&self, field: &str, value: &str) -> bool {
+        if self.field != field {
+            return false;
+        }
+        let number = value.trim().trim_end_matches('%').parse::<f64>().ok();
+        let below = self.below.is_none_or(|b| number.is_some_and(|n| n < b));
+        let above = self.above.is_none_or(|a| number.is_some_and(|n| n > a));
+        let equals = self.equals.as_ref().is_none_or(|e| e == value);
+        below && above && equals
+    }
+}
+
+// Rules every theme starts with. The user's come first, so they win.
+fn built_in_rules() -> Vec<StyleRule> {
+    let state_rule = |state: &str, style: &str| StyleRule {
+        field: String::from("STATE"), below: None, above: None, equals: Some(String::from(state)), style: String::from(style)
+    };
+    Vec::from([
+        state_rule("done", "Green"),
+        state_rule("failed", "Bold Red"),
+        state_rule("in-progress", "Yellow"),
+    ])
+}
+
+pub struct Theme {
+    styles: HashMap<String, String>,
+    rules: Vec<StyleRule>,
+    // Shade cloud cover from green, for clear scenes, to red, unless the user styles it
+    cloud_gradient: bool,
+}
+
+impl Theme {
+    /*
+     * The configured built in theme, dark unless set otherwise, with the user's
+     * per field styles and rules layered over it.
+     */
+    pub fn new(display: &DisplayConfig) -> Self {
+        let base = match display.theme.as_deref() {
+            Some("light") => LIGHT_THEME,
+            Some("dark") | None => DARK_THEME,
+            Some(other) => {
+                debug!("Theme: unknown theme {other}, using dark.");
+                DARK_THEME
+            }
+        };
+        let mut styles: HashMap<String, String> = base.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        styles.extend(display.styles.clone());
+        let cloud_gradient = !display.styles.contains_key(CLOUD_COVER);
+        let mut rules = display.rules.clone();
+        rules.extend(built_in_rules());
+        Theme { styles, rules, cloud_gradient }
+    }
+
+    /*
+     * Styles text for a field. The value is what rules are checked against, and
+     * may differ from the text, e.g. when the text is padded for a table.
+     */
+    pub fn style(&self, field: &str, value: &str, text: &str) -> String {
+        let rule = self.rules.iter().find(|r| r.matches(field, value));
+        // Only the user's rules cover cloud cover, so they win over the gradient
+        if field == CLOUD_COVER && self.cloud_gradient && rule.is_none() {
+            if let Ok(cloud) = value.trim().trim_end_matches('%').parse::<f64>() {
+                let c = cloud.clamp(0.0, 100.0) / 100.0;
+                return text.truecolor((255.0 * c) as u8, (255.0 * (1.0 - c)) as u8, 0).to_string();
+            }
+        }
+        match rule.map(|r| &r.style).or(self.styles.get(field)) {
+            Some(style) => apply_style(style, text).to_string(),
+            None => text.to_string(),
+        }
+    }
+}
+
+/*
+ * Maps a style string to Colorize calls. Unknown words are ignored.
+ */
+fn apply_style(style: &str, text: &str) -> ColoredString {
+    let mut styled = ColoredString::from(text);
+    for word in style.split_whitespace() {
+        styled = match word.to_lowercase().as_str() {
+            "bold" => styled.bold(),
+            "dimmed" => styled.dimmed(),
+            "italic" => styled.italic(),
+            "underline" => styled.underline(),
+            // Purple was the name used before themes, so keep it working
+            "purple" => styled.magenta(),
+            w => match parse_color(w) {
+                Some(color) => styled.color(color),
+                None => {
+                    debug!("Theme: unknown style {word}");
+                    styled
+                }
+            }
+        };
+    }
+    styled
+}
+
+fn parse_color(word: &str) -> Option<Color> {
+    if let Some(hex) = word.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Color::TrueColor { r: channel(0)?, g: channel(2)?, b: channel(4)? });
+    }
+    // Colorize's names, which are the ANSI ones, with Bright variants
+    match word.replace('_', "").as_str() {
+        "brightblack" => Some(Color::BrightBlack),
+        "brightred" => Some(Color::BrightRed),
+        "brightgreen" => Some(Color::BrightGreen),
+        "brightyellow" => Some(Color::BrightYellow),
+        "brightblue" => Some(Color::BrightBlue),
+        "brightmagenta" => Some(Color::BrightMagenta),
+        "brightcyan" => Some(Color::BrightCyan),
+        "brightwhite" => Some(Color::BrightWhite),
+        w => w.parse::<Color>().ok(),
+    }
+}
+
+/*
+ * Decides whether to color output at all. Auto only colors a terminal, and
+ * respects NO_COLOR (https://no-color.org).
+ */
+pub fn apply_color_choice(choice: &ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => stdout().is_terminal() && var("NO_COLOR").map(|v| v.is_empty()).unwrap_or(true),
+    };
+    colored::control::set_override(enabled);
+}
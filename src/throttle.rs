@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, NaiveTime, TimeDelta};
+use log::debug;
+use tokio::time::sleep;
+
+use crate::progress;
+
+/*
+ * Keeps downloads from taking the whole link: a cap on the transfer rate, and
+ * hours outside which downloads don't start.
+ */
+
+/*
+ * Limits the average rate of a transfer by sleeping whenever it gets ahead of
+ * where the rate says it should be. Averaged over the whole transfer, so a slow
+ * start allows a short burst after.
+ */
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    start: Instant,
+    bytes: u64,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        RateLimiter { bytes_per_second, start: Instant::now(), bytes: 0 }
+    }
+
+    // Records bytes transferred, waiting if that's more than the rate allows so far
+    pub async fn consumed(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+        let expected = Duration::from_secs_f64(self.bytes as f64 / self.bytes_per_second as f64);
+        let elapsed = self.start.elapsed();
+        if expected > elapsed {
+            sleep(expected - elapsed).await;
+        }
+    }
+}
+
+/*
+ * Parses a rate like curl's --limit-rate: bytes per second, with an optional K,
+ * M or G suffix in powers of 1024, e.g. 20M.
+ */
+pub fn parse_rate(s: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let s = s.trim();
+    let (number, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1024.0),
+        Some('M') => (&s[..s.len() - 1], 1024.0 * 1024.0),
+        Some('G') => (&s[..s.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (s, 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n > 0.0 => Ok((n * multiplier) as u64),
+        _ => Err(format!("Unable to parse rate: {s}, e.g. 500K or 20M").into()),
+    }
+}
+
+/*
+ * Hours of the day downloads may start in, local time. Windows can run past
+ * midnight, e.g. 22:00-06:00.
+ */
+#[derive(Clone, Debug)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /*
+     * Waits until the window opens, if it isn't open already. A download that's
+     * started carries on past the end of the window, since the server won't hold
+     * a connection open for hours.
+     */
+    pub async fn wait_until_open(&self) {
+        let now = Local::now().time();
+        if self.contains(now) {
+            return;
+        }
+        let mut wait: TimeDelta = self.start - now;
+        if wait < TimeDelta::zero() {
+            wait += TimeDelta::days(1);
+        }
+        progress::message(&format!(
+            "Waiting {}h{:02}m for the download window to open at {}...", wait.num_hours(), wait.num_minutes() % 60, self.start.format("%H:%M")
+        ));
+        debug!("Throttle: sleeping {wait:?}");
+        sleep(wait.to_std().unwrap_or_default()).await;
+    }
+}
+
+// Parses a window as HH:MM-HH:MM, e.g. 22:00-06:00
+pub fn parse_time_window(s: &str) -> Result<TimeWindow, Box<dyn Error + Send + Sync>> {
+    let (start, end) = s.split_once('-').ok_or(format!("Unable to parse window: {s}, e.g. 22:00-06:00"))?;
+    let time = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|_| format!("Unable to parse time: {t}"));
+    let window = TimeWindow { start: time(start)?, end: time(end)? };
+    // Could mean always or never, so neither is guessed
+    if window.start == window.end {
+        return Err(format!("Window start and end are the same: {s}").into());
+    }
+    Ok(window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(t: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(t, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn windows_run_past_midnight() {
+        let window = parse_time_window("22:00-06:00").unwrap();
+        assert!(window.contains(time("22:00")));
+        assert!(window.contains(time("23:59")));
+        assert!(window.contains(time("00:00")));
+        assert!(window.contains(time("05:59")));
+        assert!(!window.contains(time("06:00")));
+        assert!(!window.contains(time("12:00")));
+        assert!(!window.contains(time("21:59")));
+    }
+
+    #[test]
+    fn windows_within_a_day() {
+        let window = parse_time_window("09:00-17:30").unwrap();
+        assert!(window.contains(time("09:00")));
+        assert!(!window.contains(time("17:30")));
+        assert!(!window.contains(time("08:59")));
+    }
+
+    #[test]
+    fn refuses_bad_windows() {
+        let error = parse_time_window("22:00-22:00").unwrap_err();
+        assert_eq!(error.to_string(), "Window start and end are the same: 22:00-22:00");
+        assert!(parse_time_window("22:00").is_err());
+        assert!(parse_time_window("25:00-06:00").is_err());
+    }
+
+    #[test]
+    fn parses_rate_suffixes() {
+        assert_eq!(parse_rate("500").unwrap(), 500);
+        assert_eq!(parse_rate("500K").unwrap(), 500 * 1024);
+        assert_eq!(parse_rate("20m").unwrap(), 20 * 1024 * 1024);
+        assert_eq!(parse_rate("1.5G").unwrap(), 1536 * 1024 * 1024);
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("-1M").is_err());
+        assert!(parse_rate("fast").is_err());
+    }
+}
@@ -0,0 +1,384 @@
+use std::error::Error;
+
+/*
+ * Sentinel-2 tile index. Tiles follow the MGRS 100km grid, e.g. 18TVK is UTM
+ * zone 18, latitude band T, 100km square VK, so rather than bundling the ESA
+ * tile KML (tens of MB), footprints are computed from the grid definition. Each
+ * tile is 109.8km square, anchored at the 100km square's north west corner, so
+ * it overlaps its neighbours to the east and south.
+ */
+
+const TILE_SIZE: f64 = 109_800.0;
+const SQUARE_SIZE: f64 = 100_000.0;
+
+// WGS84 / UTM constants
+const A: f64 = 6_378_137.0;
+const F: f64 = 1.0 / 298.257_223_563;
+const K0: f64 = 0.9996;
+const FALSE_EASTING: f64 = 500_000.0;
+const FALSE_NORTHING: f64 = 10_000_000.0;
+
+// MGRS letters. I and O are never used.
+const BANDS: &str = "CDEFGHJKLMNPQRSTUVWX";
+const COLUMN_SETS: [&str; 3] = ["ABCDEFGH", "JKLMNPQR", "STUVWXYZ"];
+const ROWS: &str = "ABCDEFGHJKLMNPQRSTUV";
+// Lowest northing within each band, used to place the 2000km repeating row letters
+const BAND_MIN_NORTHING: [f64; 20] = [
+    1_100_000.0, 2_000_000.0, 2_800_000.0, 3_700_000.0, 4_600_000.0, 5_500_000.0, 6_400_000.0,
+    7_300_000.0, 8_200_000.0, 9_100_000.0, 0.0, 800_000.0, 1_700_000.0, 2_600_000.0, 3_500_000.0,
+    4_400_000.0, 5_300_000.0, 6_200_000.0, 7_000_000.0, 7_900_000.0,
+];
+
+/*
+ * A tile and its footprint, as [min lon, min lat, max lon, max lat] and as the
+ * ring of corners, starting north west and going clockwise.
+ */
+#[derive(Debug, Clone)]
+pub struct Tile {
+    pub name: String,
+    pub bbox: [f64; 4],
+    pub footprint: Vec<[f64; 2]>,
+}
+
+/*
+ * Checks a tile name and normalizes it, e.g. t18tvk -> 18TVK. Used to validate
+ * CLI input.
+ */
+pub fn parse_tile_name(s: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let name = s.trim().trim_start_matches(['T', 't']).to_uppercase();
+    if name.len() != 5 || decode(&name).is_none() {
+        return Err(format!("Not a Sentinel-2 tile: {s}").into());
+    }
+    Ok(name)
+}
+
+// Resolves a tile name to its footprint
+pub fn tile(name: &str) -> Result<Tile, Box<dyn Error>> {
+    let (zone, south, easting, northing) = decode(name).ok_or(format!("Not a Sentinel-2 tile: {name}"))?;
+    // North west corner, then clockwise
+    let top = northing + SQUARE_SIZE;
+    let corners = [
+        (easting, top),
+        (easting + TILE_SIZE, top),
+        (easting + TILE_SIZE, top - TILE_SIZE),
+        (easting, top - TILE_SIZE),
+    ];
+    let footprint: Vec<[f64; 2]> = corners.iter()
+        .map(|&(e, n)| {
+            let (lon, lat) = to_lon_lat(zone, south, e, n);
+            [lon, lat]
+        })
+        .collect();
+    Ok(Tile { name: name.to_string(), bbox: bounds(&footprint), footprint })
+}
+
+/*
+ * Bounding box around several tiles, for the spatial part of a search.
+ */
+pub fn tiles_bbox(names: &[String]) -> Result<[f64; 4], Box<dyn Error>> {
+    let mut corners: Vec<[f64; 2]> = Vec::new();
+    for name in names {
+        corners.extend(tile(name)?.footprint);
+    }
+    Ok(bounds(&corners))
+}
+
+/*
+ * Finds tiles whose footprint intersects a bbox of [min lon, min lat, max lon,
+ * max lat]. Works through each UTM zone the bbox spans, collecting the 100km
+ * squares near it, then checks the actual footprints. A bbox across the
+ * antimeridian comes in as one spanning the rest of the world, so bboxes over
+ * half the world wide are refused rather than listing every tile in between.
+ */
+pub fn tiles_covering(bbox: &[f64; 4]) -> Result<Vec<Tile>, Box<dyn Error>> {
+    let [min_lon, min_lat, max_lon, max_lat] = *bbox;
+    if max_lon - min_lon > 180.0 {
+        return Err(format!(
+            "The bbox spans {:.1}° of longitude. Areas across the antimeridian need a bbox either side of it.",
+            max_lon - min_lon
+        ).into());
+    }
+    let mut hemispheres: Vec<bool> = Vec::new();
+    if max_lat >= 0.0 {
+        hemispheres.push(false);
+    }
+    if min_lat < 0.0 {
+        hemispheres.push(true);
+    }
+    // Zones by longitude, plus any widened zones around Norway and Svalbard
+    let mut zones: Vec<u32> = (zone_for(min_lon, 0.0)..=zone_for(max_lon, 0.0)).collect();
+    for (lon, lat) in edge_points(bbox) {
+        let zone = zone_for(lon, lat);
+        if !zones.contains(&zone) {
+            zones.push(zone);
+        }
+    }
+    let mut found: Vec<Tile> = Vec::new();
+    for zone in zones {
+        for &south in &hemispheres {
+            for t in tiles_in_zone(zone, south, bbox) {
+                if !found.iter().any(|f| f.name == t.name) {
+                    found.push(t);
+                }
+            }
+        }
+    }
+    found.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(found)
+}
+
+// Points along the edges of a bbox, since UTM bends lines of latitude and longitude
+fn edge_points(bbox: &[f64; 4]) -> Vec<(f64, f64)> {
+    let [min_lon, min_lat, max_lon, max_lat] = *bbox;
+    let steps = 10;
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    for i in 0..=steps {
+        let t = i as f64 / steps as f64;
+        let lon = min_lon + (max_lon - min_lon) * t;
+        let lat = min_lat + (max_lat - min_lat) * t;
+        points.extend([(lon, min_lat), (lon, max_lat), (min_lon, lat), (max_lon, lat)]);
+    }
+    points
+}
+
+fn tiles_in_zone(zone: u32, south: bool, bbox: &[f64; 4]) -> Vec<Tile> {
+    let projected: Vec<(f64, f64)> = edge_points(bbox).iter().map(|&(lon, lat)| to_utm(zone, south, lon, lat)).collect();
+    let min_e = projected.iter().map(|p| p.0).fold(f64::MAX, f64::min);
+    let max_e = projected.iter().map(|p| p.0).fold(f64::MIN, f64::max);
+    let min_n = projected.iter().map(|p| p.1).fold(f64::MAX, f64::min);
+    let max_n = projected.iter().map(|p| p.1).fold(f64::MIN, f64::max);
+
+    // Tiles reach 9.8km past their square to the east and south, so look one
+    // square further west and north than the bbox itself.
+    let first_col = ((min_e - TILE_SIZE) / SQUARE_SIZE).floor().max(1.0) as i64;
+    let last_col = (max_e / SQUARE_SIZE).floor().min(8.0) as i64;
+    let first_row = (min_n / SQUARE_SIZE).floor() as i64;
+    let last_row = ((max_n + TILE_SIZE) / SQUARE_SIZE).floor() as i64;
+
+    let mut found: Vec<Tile> = Vec::new();
+    for col in first_col..=last_col {
+        for row in first_row..=last_row {
+            let (easting, northing) = (col as f64 * SQUARE_SIZE, row as f64 * SQUARE_SIZE);
+            let name = if let Some(name) = encode(zone, south, easting, northing) { name } else { continue };
+            if let Ok(t) = tile(&name) {
+                if intersects(&t.bbox, bbox) {
+                    found.push(t);
+                }
+            }
+        }
+    }
+    found
+}
+
+fn intersects(a: &[f64; 4], b: &[f64; 4]) -> bool {
+    a[0] <= b[2] && b[0] <= a[2] && a[1] <= b[3] && b[1] <= a[3]
+}
+
+fn bounds(points: &[[f64; 2]]) -> [f64; 4] {
+    points.iter().fold([f64::MAX, f64::MAX, f64::MIN, f64::MIN], |b, p| {
+        [b[0].min(p[0]), b[1].min(p[1]), b[2].max(p[0]), b[3].max(p[1])]
+    })
+}
+
+/*
+ * The UTM zone for a position. Zones are 6° wide, except that 32V is widened
+ * over south west Norway, and over Svalbard 31X, 33X, 35X and 37X are widened
+ * in place of 32X, 34X and 36X.
+ */
+fn zone_for(lon: f64, lat: f64) -> u32 {
+    if (56.0..64.0).contains(&lat) && (3.0..12.0).contains(&lon) {
+        return 32;
+    }
+    if (72.0..84.0).contains(&lat) && (0.0..42.0).contains(&lon) {
+        return match lon {
+            l if l < 9.0 => 31,
+            l if l < 21.0 => 33,
+            l if l < 33.0 => 35,
+            _ => 37,
+        };
+    }
+    (((lon + 180.0) / 6.0).floor() as u32 + 1).clamp(1, 60)
+}
+
+// Leaves out the zones Svalbard's widened ones replace
+fn zone_exists(zone: u32, band: usize) -> bool {
+    !(&BANDS[band..band + 1] == "X" && matches!(zone, 32 | 34 | 36))
+}
+
+/*
+ * MGRS name -> (zone, southern hemisphere, easting, northing) of the 100km
+ * square's south west corner.
+ */
+fn decode(name: &str) -> Option<(u32, bool, f64, f64)> {
+    let zone: u32 = name.get(0..2)?.parse().ok()?;
+    let band = BANDS.find(name.get(2..3)?)?;
+    let column = name.get(3..4)?;
+    let row = ROWS.find(name.get(4..5)?)?;
+    if !(1..=60).contains(&zone) || !zone_exists(zone, band) {
+        return None;
+    }
+    let set = COLUMN_SETS[(zone as usize - 1) % 3];
+    let easting = (set.find(column)? + 1) as f64 * SQUARE_SIZE;
+    // Row letters repeat every 2000km, and start five letters in for even zones
+    let offset = if zone.is_multiple_of(2) { 5 } else { 0 };
+    let mut northing = ((row + 20 - offset) % 20) as f64 * SQUARE_SIZE;
+    while northing < BAND_MIN_NORTHING[band] {
+        northing += 2_000_000.0;
+    }
+    Some((zone, band < 10, easting, northing))
+}
+
+/*
+ * The reverse of decode, for the 100km square containing a UTM position. The band
+ * is taken from the middle of the square.
+ */
+fn encode(zone: u32, south: bool, easting: f64, northing: f64) -> Option<String> {
+    let col = (easting / SQUARE_SIZE).floor() as usize;
+    if !(1..=8).contains(&col) || northing < 0.0 {
+        return None;
+    }
+    let set = COLUMN_SETS[(zone as usize - 1) % 3];
+    let offset = if zone.is_multiple_of(2) { 5 } else { 0 };
+    let row = ((northing / SQUARE_SIZE).floor() as usize + offset) % 20;
+    let (_, lat) = to_lon_lat(zone, south, easting + SQUARE_SIZE / 2.0, northing + SQUARE_SIZE / 2.0);
+    if !(-80.0..84.0).contains(&lat) || south != (lat < 0.0) {
+        return None;
+    }
+    let band = (((lat + 80.0) / 8.0).floor() as usize).min(BANDS.len() - 1);
+    if !zone_exists(zone, band) {
+        return None;
+    }
+    Some(format!(
+        "{zone:02}{}{}{}",
+        &BANDS[band..band + 1], &set[col - 1..col], &ROWS[row..row + 1]
+    ))
+}
+
+// Transverse Mercator, using the series from Snyder's Map Projections manual
+
+fn central_meridian(zone: u32) -> f64 {
+    (zone as f64 * 6.0 - 183.0).to_radians()
+}
+
+fn to_lon_lat(zone: u32, south: bool, easting: f64, northing: f64) -> (f64, f64) {
+    let e2 = F * (2.0 - F);
+    let ep2 = e2 / (1.0 - e2);
+    let x = easting - FALSE_EASTING;
+    let y = if south { northing - FALSE_NORTHING } else { northing };
+
+    let m = y / K0;
+    let mu = m / (A * (1.0 - e2 / 4.0 - 3.0 * e2.powi(2) / 64.0 - 5.0 * e2.powi(3) / 256.0));
+    let e1 = (1.0 - (1.0 - e2).sqrt()) / (1.0 + (1.0 - e2).sqrt());
+    let phi1 = mu
+        + (3.0 * e1 / 2.0 - 27.0 * e1.powi(3) / 32.0) * (2.0 * mu).sin()
+        + (21.0 * e1.powi(2) / 16.0 - 55.0 * e1.powi(4) / 32.0) * (4.0 * mu).sin()
+        + (151.0 * e1.powi(3) / 96.0) * (6.0 * mu).sin()
+        + (1097.0 * e1.powi(4) / 512.0) * (8.0 * mu).sin();
+
+    let sin1 = phi1.sin();
+    let n1 = A / (1.0 - e2 * sin1.powi(2)).sqrt();
+    let t1 = phi1.tan().powi(2);
+    let c1 = ep2 * phi1.cos().powi(2);
+    let r1 = A * (1.0 - e2) / (1.0 - e2 * sin1.powi(2)).powf(1.5);
+    let d = x / (n1 * K0);
+
+    let lat = phi1 - (n1 * phi1.tan() / r1) * (
+        d.powi(2) / 2.0
+        - (5.0 + 3.0 * t1 + 10.0 * c1 - 4.0 * c1.powi(2) - 9.0 * ep2) * d.powi(4) / 24.0
+        + (61.0 + 90.0 * t1 + 298.0 * c1 + 45.0 * t1.powi(2) - 252.0 * ep2 - 3.0 * c1.powi(2)) * d.powi(6) / 720.0
+    );
+    let lon = central_meridian(zone) + (
+        d
+        - (1.0 + 2.0 * t1 + c1) * d.powi(3) / 6.0
+        + (5.0 - 2.0 * c1 + 28.0 * t1 - 3.0 * c1.powi(2) + 8.0 * ep2 + 24.0 * t1.powi(2)) * d.powi(5) / 120.0
+    ) / phi1.cos();
+    (lon.to_degrees(), lat.to_degrees())
+}
+
+fn to_utm(zone: u32, south: bool, lon: f64, lat: f64) -> (f64, f64) {
+    let e2 = F * (2.0 - F);
+    let ep2 = e2 / (1.0 - e2);
+    let phi = lat.to_radians();
+    let n = A / (1.0 - e2 * phi.sin().powi(2)).sqrt();
+    let t = phi.tan().powi(2);
+    let c = ep2 * phi.cos().powi(2);
+    let a = phi.cos() * (lon.to_radians() - central_meridian(zone));
+    let m = A * (
+        (1.0 - e2 / 4.0 - 3.0 * e2.powi(2) / 64.0 - 5.0 * e2.powi(3) / 256.0) * phi
+        - (3.0 * e2 / 8.0 + 3.0 * e2.powi(2) / 32.0 + 45.0 * e2.powi(3) / 1024.0) * (2.0 * phi).sin()
+        + (15.0 * e2.powi(2) / 256.0 + 45.0 * e2.powi(3) / 1024.0) * (4.0 * phi).sin()
+        - (35.0 * e2.powi(3) / 3072.0) * (6.0 * phi).sin()
+    );
+
+    let easting = FALSE_EASTING + K0 * n * (
+        a + (1.0 - t + c) * a.powi(3) / 6.0
+        + (5.0 - 18.0 * t + t.powi(2) + 72.0 * c - 58.0 * ep2) * a.powi(5) / 120.0
+    );
+    let northing = K0 * (m + n * phi.tan() * (
+        a.powi(2) / 2.0
+        + (5.0 - t + 9.0 * c + 4.0 * c.powi(2)) * a.powi(4) / 24.0
+        + (61.0 - 58.0 * t + t.powi(2) + 600.0 * c - 330.0 * ep2) * a.powi(6) / 720.0
+    ));
+    (easting, if south { northing + FALSE_NORTHING } else { northing })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names_covering(bbox: [f64; 4]) -> Vec<String> {
+        tiles_covering(&bbox).unwrap().into_iter().map(|t| t.name).collect()
+    }
+
+    // A tiny bbox around a point
+    fn at(lon: f64, lat: f64) -> [f64; 4] {
+        [lon, lat, lon + 0.0001, lat + 0.0001]
+    }
+
+    #[test]
+    fn finds_tiles_for_known_points() {
+        assert_eq!(names_covering(at(2.3522, 48.8566)), ["31UDQ"]);
+        assert_eq!(names_covering(at(151.2093, -33.8688)), ["56HLH"]);
+        assert_eq!(names_covering([-76.2, 39.5, -75.9, 39.9]), ["18SUJ", "18SVJ", "18TUK", "18TVK"]);
+    }
+
+    #[test]
+    fn resolves_tile_footprints() {
+        let t = tile("18TVK").unwrap();
+        let expected = [-76.1827, 39.6558, -74.8841, 40.6508];
+        assert!(t.bbox.iter().zip(expected).all(|(a, b)| (a - b).abs() < 0.001), "{:?}", t.bbox);
+        assert_eq!(t.footprint.len(), 4);
+        // Names round trip through the grid
+        assert!(names_covering(t.bbox).contains(&String::from("18TVK")));
+    }
+
+    #[test]
+    fn follows_the_norway_and_svalbard_zones() {
+        // South west Norway is in the widened 32V
+        assert!(names_covering(at(5.0, 60.0)).contains(&String::from("32VKM")));
+        assert_eq!(names_covering(at(8.0, 78.0)), ["31XFG"]);
+        assert_eq!(names_covering(at(10.0, 78.0)), ["33XUG"]);
+        // Zones replaced by Svalbard's wider ones don't exist
+        assert!(parse_tile_name("32XNA").is_err());
+        assert!(parse_tile_name("34XEA").is_err());
+        assert!(parse_tile_name("33XVG").is_ok());
+    }
+
+    #[test]
+    fn covers_both_sides_of_a_zone_boundary() {
+        assert_eq!(names_covering([-78.2, 39.0, -77.8, 39.2]), ["17SQD", "18STJ"]);
+    }
+
+    #[test]
+    fn refuses_bboxes_across_the_antimeridian() {
+        assert!(tiles_covering(&[-179.5, -17.0, 179.5, -16.0]).is_err());
+        assert!(names_covering([179.5, -17.0, 180.0, -16.0]).iter().all(|n| n.starts_with("60")));
+    }
+
+    #[test]
+    fn normalizes_tile_names() {
+        assert_eq!(parse_tile_name("t18tvk").unwrap(), "18TVK");
+        assert!(parse_tile_name("18TV").is_err());
+        assert!(parse_tile_name("61TVK").is_err());
+    }
+}
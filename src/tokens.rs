@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, OsRng};
+use chrono::{DateTime, Local};
+use log::debug;
+
+use crate::api::AuthDetails;
+use crate::credentials::{KEYRING_SERVICE, keyring_lookup, keyring_set};
+use crate::progress;
+use crate::util::{config_dir, write_private};
+
+// Tokens live in their own file rather than the config, so they can be locked down
+const TOKENS_FILE: &str = "tokens";
+// Marks an encrypted tokens file. Followed by the nonce, then the ciphertext.
+const ENCRYPTED_MAGIC: &[u8] = b"CPTK1";
+const NONCE_LENGTH: usize = 12;
+// AES-256
+const KEY_LENGTH: usize = 32;
+// Tokens saved without --profile are kept under this name
+pub const DEFAULT_PROFILE: &str = "default";
+
+/*
+ * Stored tokens, keyed by profile name. When encryption is enabled, the file is
+ * AES-GCM encrypted with a key kept in the OS keyring.
+ */
+pub struct TokenStore {
+    pub encrypt: bool,
+}
+
+impl TokenStore {
+    pub fn path(&self) -> Result<PathBuf, Box<dyn Error>> {
+        Ok(config_dir()?.join(TOKENS_FILE))
+    }
+
+    pub async fn load_all(&self) -> Result<HashMap<String, AuthDetails>, Box<dyn Error>> {
+        match self.read().await? {
+            Some((contents, key)) => parse_tokens(&contents, key.as_deref()),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    /*
+     * Reads the file and, if it's encrypted, the key for it. Files are read
+     * according to what they are rather than the current setting, so turning
+     * encryption on or off just takes effect on the next save.
+     */
+    async fn read(&self) -> Result<Option<(Vec<u8>, Option<Vec<u8>>)>, Box<dyn Error>> {
+        let path = self.path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read(&path)?;
+        let key = if contents.starts_with(ENCRYPTED_MAGIC) { Some(encryption_key().await?) } else { None };
+        Ok(Some((contents, key)))
+    }
+
+    pub async fn save_all(&self, tokens: &HashMap<String, AuthDetails>) -> Result<(), Box<dyn Error>> {
+        let json = serde_json::to_vec(tokens)?;
+        let contents = if self.encrypt {
+            [ENCRYPTED_MAGIC, &encrypt(&encryption_key().await?, &json)?].concat()
+        } else {
+            json
+        };
+        write_private(&self.path()?, &contents)
+    }
+
+    pub async fn load(&self, profile: &str) -> Result<Option<AuthDetails>, Box<dyn Error>> {
+        Ok(self.load_all().await?.remove(profile))
+    }
+
+    pub async fn save(&self, profile: &str, auth_details: &AuthDetails) -> Result<(), Box<dyn Error>> {
+        let mut tokens = self.load_all().await?;
+        tokens.insert(profile.to_string(), auth_details.clone());
+        self.save_all(&tokens).await
+    }
+
+    /*
+     * Removes tokens for one profile, or all of them. Returns what was removed so
+     * callers can revoke the sessions too. When removing all of them, a file that
+     * can't be read, e.g. corrupt or encrypted with a lost key, is deleted
+     * outright, since that's the only way to recover from it. Its sessions just
+     * expire on the server. A keyring that's locked or not running isn't a reason
+     * to, so that's left as an error.
+     */
+    pub async fn remove(&self, profile: Option<&str>) -> Result<Vec<AuthDetails>, Box<dyn Error>> {
+        let Some((contents, key)) = self.read().await? else {
+            return Ok(Vec::new());
+        };
+        let mut tokens = match (parse_tokens(&contents, key.as_deref()), profile) {
+            (Ok(tokens), _) => tokens,
+            (Err(e), None) => {
+                progress::message(&format!("Deleting unreadable stored tokens: {e}"));
+                fs::remove_file(self.path()?)?;
+                return Ok(Vec::new());
+            },
+            (Err(e), Some(_)) => {
+                return Err(format!("Unable to read stored tokens: {e}. Run `auth logout --all` to delete them.").into());
+            },
+        };
+        let removed = match profile {
+            Some(profile) => tokens.remove(profile).into_iter().collect(),
+            None => tokens.drain().map(|(_, v)| v).collect(),
+        };
+        if tokens.is_empty() {
+            let path = self.path()?;
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        } else {
+            self.save_all(&tokens).await?;
+        }
+        Ok(removed)
+    }
+}
+
+fn parse_tokens(contents: &[u8], key: Option<&[u8]>) -> Result<HashMap<String, AuthDetails>, Box<dyn Error>> {
+    let json = match key {
+        Some(key) => decrypt(key, &contents[ENCRYPTED_MAGIC.len()..])?,
+        None => contents.to_vec(),
+    };
+    Ok(serde_json::from_slice(&json)?)
+}
+
+fn encryption_key_attributes() -> HashMap<&'static str, &'static str> {
+    HashMap::from([("service", KEYRING_SERVICE), ("purpose", "token-encryption")])
+}
+
+/*
+ * Gets the token encryption key from the keyring, generating one on first use.
+ * Unlike passwords there's no file fallback here: a key stored next to the file
+ * it encrypts wouldn't protect anything.
+ */
+async fn encryption_key() -> Result<Vec<u8>, Box<dyn Error>> {
+    let existing = keyring_lookup(encryption_key_attributes()).await
+        .map_err(|e| format!("Token encryption needs an OS keyring, which isn't available: {e}"))?;
+    match existing {
+        Some(key) => Ok(key),
+        None => {
+            debug!("Tokens: generating new encryption key.");
+            let key = Aes256Gcm::generate_key(OsRng).to_vec();
+            keyring_set("Copernicus CLI token key", encryption_key_attributes(), &key).await?;
+            Ok(key)
+        }
+    }
+}
+
+fn encrypt(key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if key.len() != KEY_LENGTH {
+        return Err(format!("The token encryption key in the keyring is {} bytes, not {KEY_LENGTH}.", key.len()).into());
+    }
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plaintext).map_err(|e| format!("Unable to encrypt tokens: {e}"))?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn decrypt(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if data.len() < NONCE_LENGTH || key.len() != KEY_LENGTH {
+        return Err("Stored tokens are corrupt or the key is wrong. Run `auth logout --all` to reset them.".into());
+    }
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+    cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Unable to decrypt stored tokens. Run `auth logout --all` to reset them.".into())
+}
+
+/*
+ * Summary of a profile's stored tokens for `auth status`.
+ */
+pub fn format_token_status(profile: &str, auth_details: &AuthDetails) -> String {
+    let expiry = |seconds: i64| {
+        let at = DateTime::from_timestamp(auth_details.acquired_time + seconds, 0).unwrap_or_default();
+        let state = if at < chrono::Utc::now() { "expired" } else { "valid" };
+        format!("{} ({state})", at.with_timezone(&Local).format("%F %T %Z"))
+    };
+    let refresh = if auth_details.refresh_token.is_empty() {
+        String::from("none")
+    } else {
+        expiry(auth_details.refresh_expires_in)
+    };
+    format!(
+        "{profile}\n  access token expires:  {}\n  refresh token expires: {refresh}",
+        expiry(auth_details.expires_in as i64),
+    )
+}
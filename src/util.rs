@@ -1,41 +1,38 @@
 use std::collections::HashMap;
 use std::error::Error;
-use std::io::{stdout, Write};
+use std::fs::{create_dir_all, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use chrono::offset::Utc;
 use chrono::{DateTime, NaiveDate};
-use colored::Colorize;
+use geo::{ChamberlainDuquetteArea, MultiPolygon};
 use geojson::{Feature, FeatureCollection};
 use geojson::feature::Id;
 use geojson::JsonObject;
 use geojson::JsonValue;
+use serde::{Serialize, Deserialize};
 use serde_json::Map;
 use serde_json::Value;
 
-use crate::args::TimeAdjust;
-/*
- * Hardcoded style information for List and Search outputs. At the moment these
- * are all set to conservative (read: useless?) values.
- * TODO: Refine this.
- */
-const STYLES: [(&str, &str); 9] = [
-    ("ID", "White"),
-    ("SHORT_NAME", "White"),
-    ("SERIAL", "White"),
-    ("DETAIL", "White"),
-    ("CAPTURE_TIME", "White"),
-    ("CLOUD_COVER", "White"),
-    ("BBOX", "White"),
-    ("QUICKLOOK_HREF", "White"),
-    ("PRODUCT_HREF", "White"),
-];
+use crate::APP_NAME;
+use crate::api::Collection;
+use crate::archive::ProductInfo;
+use crate::args::{SearchArgs, TimeAdjust};
+use crate::coverage::{CoverageReport, aoi_bbox};
+use crate::dates::parse_relative;
+use crate::plan::DownloadPlan;
+use crate::queue::Queue;
+use crate::table::format_table;
+use crate::theme::{StyleRule, Theme};
+use crate::tiles::Tile;
 
 /*
- * Singular template to use for listing features
- * TODO: Use different templates for different types of features
+ * Default template to use for listing features, for collections without one of
+ * their own below.
  */
 const FEATURE_DETAILS_FORMAT: &str = r#"
-<ID> (<SHORT_NAME>.<SERIAL>/<DETAIL>)
+<INDEX>. <ID> (<SHORT_NAME>.<SERIAL>/<DETAIL>)
   <CAPTURE_TIME> cloudy: <CLOUD_COVER>
   bbox: <BBOX>
   quicklook: <QUICKLOOK_HREF>
@@ -43,38 +40,135 @@ const FEATURE_DETAILS_FORMAT: &str = r#"
 "#;
 
 /*
- * Function to map color values to Colorize function calls, which colors output
- * strings. This might not include all the available colors from Colorize, just
- * those I tested with.
- */
-fn style_value(k: &str, v: String, styles: &HashMap<&str, &str>) -> String {
-    let style = styles.get(k);
-    match style {
-        Some(s) => {
-            match *s {
-                "White" => v.as_str().white().to_string(),
-                "BrightWhite" => v.as_str().bright_white().to_string(),
-                "BrightBlack" => v.as_str().bright_black().to_string(),
-                "Green" => v.as_str().green().to_string(),
-                "Cyan" => v.as_str().cyan().to_string(),
-                "BrightCyan" => v.as_str().bright_cyan().to_string(),
-                "Blue" => v.as_str().blue().to_string(),
-                "Purple" => v.as_str().purple().to_string(),
-                "BrightBlue" => v.as_str().bright_blue().to_string(),
-                "Red" => v.as_str().red().to_string(),
-                _ => v
-            }
-        },
-        None => v,
+ * Per collection templates. Sentinel-1 is radar, so cloud cover means nothing and
+ * the acquisition geometry matters instead. Sentinel-2 is organized by tile.
+ */
+const COLLECTION_FEATURE_FORMATS: [(&str, &str); 4] = [
+    ("SENTINEL-1", r#"
+<INDEX>. <ID> (<SHORT_NAME>.<SERIAL>/<DETAIL>)
+  <CAPTURE_TIME> <SENSOR_MODE> <POLARISATION> orbit: <ORBIT> <ORBIT_DIRECTION>
+  bbox: <BBOX>
+  quicklook: <QUICKLOOK_HREF>
+  product: <PRODUCT_HREF>
+"#),
+    ("SENTINEL-2", r#"
+<INDEX>. <ID> (<SHORT_NAME>.<SERIAL>/<DETAIL>)
+  <CAPTURE_TIME> tile: <TILE> orbit: <ORBIT> cloudy: <CLOUD_COVER>
+  bbox: <BBOX>
+  quicklook: <QUICKLOOK_HREF>
+  product: <PRODUCT_HREF>
+"#),
+    ("SENTINEL-3", r#"
+<INDEX>. <ID> (<SHORT_NAME>.<SERIAL>/<DETAIL>)
+  <CAPTURE_TIME> <INSTRUMENT> orbit: <ORBIT> <ORBIT_DIRECTION> cloudy: <CLOUD_COVER>
+  bbox: <BBOX>
+  quicklook: <QUICKLOOK_HREF>
+  product: <PRODUCT_HREF>
+"#),
+    ("SENTINEL-5P", r#"
+<INDEX>. <ID> (<SHORT_NAME>/<DETAIL>)
+  <CAPTURE_TIME> <INSTRUMENT> orbit: <ORBIT>
+  bbox: <BBOX>
+  product: <PRODUCT_HREF>
+"#),
+];
+
+/*
+ * Display settings from the config file. Templates are keyed by collection, e.g.
+ * SENTINEL-2, or "default" for everything else, and take the same <TAG>s as the
+ * built in ones, plus any feature property by name, e.g. <processingDate>.
+ * Styles are keyed by tag too, and override the theme's, which is "dark" or
+ * "light".
+ */
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DisplayConfig {
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+    #[serde(default)]
+    pub theme: Option<String>,
+    #[serde(default)]
+    pub styles: HashMap<String, String>,
+    #[serde(default)]
+    pub rules: Vec<StyleRule>,
+}
+
+// Single line per collection, for the collections listing
+const COLLECTION_DETAILS_FORMAT: &str = "<ID>  <TITLE>";
+
+// Summary of a downloaded product archive
+const PRODUCT_DETAILS_FORMAT: &str = r#"
+<ID> (<FILES> files)
+  granules: <GRANULES>
+  bands: <BANDS>
+  measurements: <MEASUREMENTS>
+  footprint: <FOOTPRINT>
+"#;
+
+// Single line per tile, for the tiles listing
+const TILE_DETAILS_FORMAT: &str = "<ID>  bbox: <BBOX>";
+
+// Download plan, per product then totals
+const PLAN_ITEM_FORMAT: &str = "<ID>  <SIZE>  <STATUS>";
+const PLAN_SUMMARY_FORMAT: &str = r#"
+<COUNT> to download: <SIZE>, <UNKNOWN> of unknown size. <SKIPPED> already present.
+<FREE> free in <DIRECTORY>"#;
+
+// Queued downloads, one per line, with why the last attempt failed if it did
+const QUEUE_ITEM_FORMAT: &str = "<ID>  <STATE>  attempts: <ATTEMPTS>";
+const QUEUE_ERROR_FORMAT: &str = "  error: <ERROR>";
+
+// Coverage of the AOI, per feature then for all results together
+const COVERAGE_FEATURE_FORMAT: &str = "<ID>  covers: <COVERAGE>";
+const COVERAGE_SUMMARY_FORMAT: &str = "All results cover <COVERAGE> of <AREA>, with <GAPS> gap(s)";
+const COVERAGE_GAP_FORMAT: &str = "  gap: <AREA> bbox: <BBOX>";
+
+/*
+ * Byte counts in human terms, e.g. 1.2 GB.
+ */
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < units.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
     }
+    if unit == 0 { format!("{bytes} B") } else { format!("{size:.1} {}", units[unit]) }
 }
 
 /*
- * Prints over the last line, through use of \r, then flushes stdout.
+ * Directory holding our config file. Anything else we keep between runs lives
+ * alongside it, so it's all in one place for the user.
  */
-pub fn print_over(s: &str) {
-    print!("\r{s}");
-    let _ = stdout().flush();
+pub fn config_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let config_path = confy::get_configuration_file_path(APP_NAME, None)?;
+    match config_path.parent() {
+        Some(dir) => Ok(dir.to_path_buf()),
+        None => Err(format!("No directory for config path: {config_path:?}").into())
+    }
+}
+
+/*
+ * Writes a file only the current user can read or write. The mode is only
+ * applied by the OS on creation, so it's reapplied for files that already exist.
+ */
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        if path.exists() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    let mut f = options.open(path)?;
+    f.write_all(contents)?;
+    Ok(())
 }
 
 /*
@@ -147,6 +241,8 @@ pub fn parse_date(s: &str, should_adjust: Option<TimeAdjust>) -> Result<DateTime
                     // apply a value here, and this might be the most sensible for ranges.
                     None => Ok(dt.and_hms_opt(0,0,0).unwrap().and_utc()),
                 }
+            } else if let Some(dt) = parse_relative(s, should_adjust, Utc::now()) {
+                Ok(dt)
             } else {
                 Err(format!("Unable to parse: {s}").into())
             }
@@ -154,6 +250,22 @@ pub fn parse_date(s: &str, should_adjust: Option<TimeAdjust>) -> Result<DateTime
     }
 }
 
+/*
+ * Parses a bbox given as two opposite corners, e.g. top left and bottom right,
+ * into [min lon, min lat, max lon, max lat].
+ */
+pub fn parse_bbox(s: &str) -> Result<[f64; 4], Box<dyn Error + Send + Sync>> {
+    let values = s.split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| format!("Unable to parse bbox: {s}"))?;
+    if let [x1, y1, x2, y2] = values[..] {
+        Ok([x1.min(x2), y1.min(y2), x1.max(x2), y1.max(y2)])
+    } else {
+        Err(format!("A bbox needs four values: {s}").into())
+    }
+}
+
 // Display methods
 
 /*
@@ -161,10 +273,182 @@ pub fn parse_date(s: &str, should_adjust: Option<TimeAdjust>) -> Result<DateTime
  * level display function so that we can just print out whatever came back
  * for the provided query.
  */
-pub fn format_feature_collection(fc: &FeatureCollection) -> String {
+pub fn format_feature_collection(fc: &FeatureCollection, display: &DisplayConfig) -> String {
+    let theme = Theme::new(display);
     let mut output: Vec<String> = Vec::new();
-    for feature in fc.features.clone() {
-        output.push(format_feature(&feature));
+    for (i, feature) in fc.features.iter().enumerate() {
+        output.push(format_feature(feature, i + 1, display, &theme));
+    }
+    output.join("\n")
+}
+
+/*
+ * Formats search results as the search args ask, as a table or the regular
+ * listing. Results are numbered in the order given, so sort them beforehand.
+ */
+pub fn format_results(fc: &FeatureCollection, args: &SearchArgs, display: &DisplayConfig) -> String {
+    if args.table {
+        format_table(fc, &args.columns, &Theme::new(display))
+    } else {
+        format_feature_collection(fc, display)
+    }
+}
+
+// A feature's collection, e.g. SENTINEL-2, or its platform where that's missing
+pub fn collection_of(f: &Feature) -> String {
+    f.foreign_members.as_ref()
+        .and_then(|m| m.get("collection").cloned())
+        .or(f.properties.as_ref().and_then(|p| p.get("platformShortName").cloned()))
+        .and_then(|v| v.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+/*
+ * Picks the template for a feature's collection: the user's, then ours, then the
+ * user's default, then ours.
+ */
+fn feature_template<'a>(f: &Feature, display: &'a DisplayConfig) -> &'a str {
+    let collection = collection_of(f);
+    let built_in = HashMap::from(COLLECTION_FEATURE_FORMATS);
+    if let Some(template) = display.templates.get(&collection) {
+        template
+    } else if let Some(template) = built_in.get(collection.as_str()) {
+        template
+    } else if let Some(template) = display.templates.get("default") {
+        template
+    } else {
+        FEATURE_DETAILS_FORMAT
+    }
+}
+
+/*
+ * Lists collections one per line, falling back to the description where a
+ * collection has no title.
+ */
+pub fn format_collections(collections: &[Collection], display: &DisplayConfig) -> String {
+    let theme = Theme::new(display);
+    let mut output: Vec<String> = Vec::new();
+    for collection in collections {
+        let data = HashMap::from([
+            ("ID", Some(collection.id.clone())),
+            ("TITLE", collection.title.clone().or(collection.description.clone())),
+        ]);
+        output.push(format_with_template(COLLECTION_DETAILS_FORMAT, &data, &theme));
+    }
+    output.join("\n")
+}
+
+pub fn format_tiles(tiles: &[Tile], display: &DisplayConfig) -> String {
+    let theme = Theme::new(display);
+    let mut output: Vec<String> = Vec::new();
+    for tile in tiles {
+        let bbox = tile.bbox.iter().map(|v| format!("{v:.6}")).collect::<Vec<String>>().join(",");
+        let data = HashMap::from([("ID", Some(tile.name.clone())), ("BBOX", Some(bbox))]);
+        output.push(format_with_template(TILE_DETAILS_FORMAT, &data, &theme));
+    }
+    output.join("\n")
+}
+
+pub fn format_product_info(info: &ProductInfo, display: &DisplayConfig) -> String {
+    let list = |values: Vec<String>| if values.is_empty() { None } else { Some(values.join(", ")) };
+    let bands = info.bands.iter()
+        .map(|(band, resolution)| match resolution {
+            Some(r) => format!("{band} ({r}m)"),
+            None => band.clone(),
+        })
+        .collect();
+    let footprint = info.footprint.iter().map(|p| format!("{},{}", p[0], p[1])).collect();
+    let data = HashMap::from([
+        ("ID", Some(info.name.clone())),
+        ("FILES", Some(info.files.to_string())),
+        ("GRANULES", list(info.granules.clone())),
+        ("BANDS", list(bands)),
+        ("MEASUREMENTS", list(info.measurements.clone())),
+        ("FOOTPRINT", list(footprint)),
+    ]);
+    format_with_template(PRODUCT_DETAILS_FORMAT, &data, &Theme::new(display))
+}
+
+/*
+ * Lists how much of the AOI each feature covers, then what they cover together
+ * and where the gaps are.
+ */
+pub fn format_coverage(report: &CoverageReport, display: &DisplayConfig) -> String {
+    let theme = Theme::new(display);
+    let percent = |fraction: f64| format!("{:.1}%", fraction * 100.0);
+    let mut output: Vec<String> = Vec::new();
+    for (id, fraction) in &report.features {
+        let data = HashMap::from([("ID", Some(id.clone())), ("COVERAGE", Some(percent(*fraction)))]);
+        output.push(format_with_template(COVERAGE_FEATURE_FORMAT, &data, &theme));
+    }
+    let data = HashMap::from([
+        ("COVERAGE", Some(percent(report.union))),
+        ("AREA", Some(format_area(report.aoi_area))),
+        ("GAPS", Some(report.gaps.0.len().to_string())),
+    ]);
+    output.push(format_with_template(COVERAGE_SUMMARY_FORMAT, &data, &theme));
+    for gap in &report.gaps.0 {
+        let bbox = aoi_bbox(&MultiPolygon::new(Vec::from([gap.clone()])))
+            .map(|b| b.iter().map(|v| format!("{v:.6}")).collect::<Vec<String>>().join(","));
+        let data = HashMap::from([
+            ("AREA", Some(format_area(gap.chamberlain_duquette_unsigned_area()))),
+            ("BBOX", bbox),
+        ]);
+        output.push(format_with_template(COVERAGE_GAP_FORMAT, &data, &theme));
+    }
+    output.join("\n")
+}
+
+// Square meters in human terms, e.g. 12.3 km²
+fn format_area(square_meters: f64) -> String {
+    if square_meters >= 1e6 { format!("{:.1} km²", square_meters / 1e6) } else { format!("{square_meters:.0} m²") }
+}
+
+/*
+ * Lists what a download would fetch and skip, with the total size against the
+ * space available.
+ */
+pub fn format_plan(plan: &DownloadPlan, display: &DisplayConfig) -> String {
+    let theme = Theme::new(display);
+    let mut output: Vec<String> = Vec::new();
+    for item in &plan.items {
+        let status = match (item.present, item.unverified) {
+            (true, true) => "present, size unchecked, skipping",
+            (true, false) => "present, skipping",
+            (false, _) => "download",
+        };
+        let data = HashMap::from([
+            ("ID", Some(item.id.clone())),
+            ("SIZE", Some(item.size.map(format_size).unwrap_or(String::from("unknown size")))),
+            ("STATUS", Some(String::from(status))),
+        ]);
+        output.push(format_with_template(PLAN_ITEM_FORMAT, &data, &theme));
+    }
+    let data = HashMap::from([
+        ("COUNT", Some(plan.pending().count().to_string())),
+        ("SIZE", Some(format_size(plan.total_bytes()))),
+        ("UNKNOWN", Some(plan.unknown_sizes().to_string())),
+        ("SKIPPED", Some(plan.items.iter().filter(|i| i.present).count().to_string())),
+        ("FREE", plan.free_space.map(format_size).or(Some(String::from("Unknown space")))),
+        ("DIRECTORY", Some(plan.directory.display().to_string())),
+    ]);
+    output.push(format_with_template(PLAN_SUMMARY_FORMAT, &data, &theme));
+    output.join("\n")
+}
+
+pub fn format_queue(queue: &Queue, display: &DisplayConfig) -> String {
+    let theme = Theme::new(display);
+    let mut output: Vec<String> = Vec::new();
+    for item in &queue.items {
+        let data = HashMap::from([
+            ("ID", Some(item.id.clone())),
+            ("STATE", Some(item.state.name().to_string())),
+            ("ATTEMPTS", Some(item.attempts.to_string())),
+        ]);
+        output.push(format_with_template(QUEUE_ITEM_FORMAT, &data, &theme));
+        if let Some(error) = &item.error {
+            output.push(format_with_template(QUEUE_ERROR_FORMAT, &HashMap::from([("ERROR", Some(error.clone()))]), &theme));
+        }
     }
     output.join("\n")
 }
@@ -173,7 +457,7 @@ pub fn format_feature_collection(fc: &FeatureCollection) -> String {
  * Feature display method. Extracts information from the feature and passes
  * it along to the templating function to generate finalized output.
  */
-pub fn format_feature(f: &Feature) -> String {
+pub fn format_feature(f: &Feature, index: usize, display: &DisplayConfig, theme: &Theme) -> String {
     // Top level feature attributes
     let id = get_id(&f.id);
     let bbox = Some(f.bbox.clone().unwrap_or_default().iter().map(|&v| v.to_string()).collect::<Vec<String>>().join(","));
@@ -183,39 +467,79 @@ pub fn format_feature(f: &Feature) -> String {
     let serial_identifier: Option<String> = get_value(properties.get("platformSerialIdentifier").cloned());
     let product_type: Option<String> = get_value(properties.get("productType").cloned());
     let capture_time: Option<String> = get_value(properties.get("datetime").cloned());
+    let instrument: Option<String> = get_value(properties.get("instrumentShortName").cloned());
     // Atmospheric values
     let cloud_cover: Option<String> = get_value(properties.get("cloudCover").cloned());
+    // Acquisition geometry
+    let tile: Option<String> = get_value(properties.get("tileId").cloned());
+    let orbit: Option<String> = get_value(properties.get("relativeOrbitNumber").cloned());
+    let orbit_direction: Option<String> = get_value(properties.get("orbitDirection").cloned());
+    // Radar values
+    let polarisation: Option<String> = get_value(properties.get("polarisationChannels").cloned());
+    let sensor_mode: Option<String> = get_value(properties.get("operationalMode").cloned());
     // Product links
     let quicklook_href: Option<String> = get_value(from_path(Vec::from(["assets", "QUICKLOOK", "href"]), &f.foreign_members));
     let product_href: Option<String> = get_value(from_path(Vec::from(["assets", "PRODUCT", "href"]), &f.foreign_members));
-    let data = HashMap::from([
+    let mut data = HashMap::from([
+        ("INDEX", Some(index.to_string())),
         ("ID", id),
         ("SHORT_NAME", short_name),
         ("SERIAL", serial_identifier),
         ("DETAIL", product_type),
+        ("INSTRUMENT", instrument),
         ("CAPTURE_TIME", capture_time),
         ("CLOUD_COVER", cloud_cover),
+        ("TILE", tile),
+        ("ORBIT", orbit),
+        ("ORBIT_DIRECTION", orbit_direction),
+        ("POLARISATION", polarisation),
+        ("SENSOR_MODE", sensor_mode),
         ("BBOX", bbox),
         ("QUICKLOOK_HREF", quicklook_href),
         ("PRODUCT_HREF", product_href)
     ]);
-    format_with_template(FEATURE_DETAILS_FORMAT, &data)
+    // Raw properties too, for user templates
+    for (k, v) in properties {
+        data.entry(k.as_str()).or_insert(get_value(Some(v.clone())));
+    }
+    format_with_template(feature_template(f, display), &data, theme)
 }
 
 /*
- * Takes a template and a HashMap of values and interpolates them. Will also use
- * the STYLES information at the top of the file to colorize output, though how
- * useful this is depends on the end users' terminal configuration.
+ * Takes a template and a HashMap of values and interpolates them, styling each
+ * value and the text around them with the theme. Tags without a value are left
+ * as they are, and styled as text.
  */
-fn format_with_template(template: &str, data: &HashMap<&str, Option<String>>) -> String {
-    let mut compiled = String::from(template).truecolor(64, 64, 64).to_string();
-    let styles = HashMap::from(STYLES);
-    for (k, mv) in data {
-        let v = if let Some(v) = mv { v } else { &String::from("N/A") };
-        let tag = format!("<{k}>");
-        let value = style_value(k, v.clone(), &styles);
-        compiled = compiled.replace(&tag, &value);
+fn format_with_template(template: &str, data: &HashMap<&str, Option<String>>, theme: &Theme) -> String {
+    let mut compiled = String::new();
+    let mut literal = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('<') {
+        let tag = rest[open + 1..].find('>').map(|close| &rest[open + 1..open + 1 + close]);
+        match tag.and_then(|t| data.get(t).map(|v| (t, v))) {
+            Some((tag, value)) => {
+                literal.push_str(&rest[..open]);
+                compiled.push_str(&style_literal(&literal, theme));
+                literal.clear();
+                let value = value.as_deref().unwrap_or("N/A");
+                compiled.push_str(&theme.style(tag, value, value));
+                rest = &rest[open + tag.len() + 2..];
+            },
+            None => {
+                literal.push_str(&rest[..=open]);
+                rest = &rest[open + 1..];
+            }
+        }
     }
-    compiled.to_string()
+    literal.push_str(rest);
+    compiled.push_str(&style_literal(&literal, theme));
+    compiled
 }
 
+// Styles template text line by line, so line breaks aren't inside escape codes
+fn style_literal(literal: &str, theme: &Theme) -> String {
+    literal.split('\n')
+        .map(|line| if line.is_empty() { String::new() } else { theme.style("TEMPLATE", line, line) })
+        .collect::<Vec<String>>()
+        .join("\n")
+}